@@ -0,0 +1,22 @@
+// Exercise the heartbeat against the host stand-in over vsock loopback.
+// Requires the vsock_loopback module: `modprobe vsock_loopback`
+use aws::heartbeat::{serve, Heartbeat};
+use std::thread;
+use std::time::Duration;
+
+const LOOPBACK_CID: u32 = libc::VMADDR_CID_LOCAL;
+const PORT: u32 = 9000;
+
+fn main() {
+    let listener = thread::spawn(|| serve(LOOPBACK_CID, PORT, Some(1)));
+    thread::sleep(Duration::from_millis(100));
+    let heartbeat = Heartbeat { cid: LOOPBACK_CID, port: PORT, ..Default::default() };
+    match heartbeat.send() {
+        Ok(attempts) => println!("heartbeat acknowledged after {} attempt(s)", attempts),
+        Err(e) => {
+            eprintln!("{}", e.message);
+            std::process::exit(1);
+        }
+    }
+    listener.join().expect("!thread").expect("listener failed");
+}
@@ -0,0 +1,144 @@
+use std::fs::File;
+use std::io::{ErrorKind, Read, Write};
+use std::os::fd::{AsRawFd, FromRawFd};
+use std::thread;
+use std::time::Duration;
+use system::{info, socket_connect, socket_listen, warn, SystemError};
+
+// Byte the enclave sends and the host echoes back
+pub const HEARTBEAT_VALUE: u8 = 0xB7;
+// Parent instance and port the Nitro hypervisor listens on
+pub const HEARTBEAT_CID: u32 = 3;
+pub const HEARTBEAT_PORT: u32 = 9000;
+
+// Boot heartbeat sent to the host, retried until it is acknowledged
+pub struct Heartbeat {
+    pub cid: u32,
+    pub port: u32,
+    pub attempts: u32,
+    // Read and write timeout of a single attempt
+    pub timeout: Duration,
+    pub retry_delay: Duration,
+}
+
+impl Default for Heartbeat {
+    fn default() -> Self {
+        Heartbeat {
+            cid: HEARTBEAT_CID,
+            port: HEARTBEAT_PORT,
+            attempts: 5,
+            timeout: Duration::from_secs(2),
+            retry_delay: Duration::from_millis(500),
+        }
+    }
+}
+
+impl Heartbeat {
+    // Send one heartbeat and wait for the host to echo it
+    pub fn send_once(&self) -> Result<(), SystemError> {
+        use libc::AF_VSOCK;
+        let fd = socket_connect(AF_VSOCK, self.port, self.cid)?;
+        let mut stream = unsafe { File::from_raw_fd(fd) };
+        set_timeouts(&stream, self.timeout)?;
+        stream.write_all(&[HEARTBEAT_VALUE]).map_err(|e| SystemError {
+            message: format!("Failed to send heartbeat: {}", e),
+        })?;
+        let mut ack = [0u8; 1];
+        stream.read_exact(&mut ack).map_err(|e| SystemError {
+            message: match e.kind() {
+                ErrorKind::WouldBlock | ErrorKind::TimedOut => format!(
+                    "No heartbeat acknowledgement within {} ms",
+                    self.timeout.as_millis()
+                ),
+                _ => format!("Failed to read heartbeat acknowledgement: {}", e),
+            },
+        })?;
+        if ack[0] != HEARTBEAT_VALUE {
+            return Err(SystemError {
+                message: format!(
+                    "Heartbeat acknowledged with {:#04x}, expected {:#04x}",
+                    ack[0], HEARTBEAT_VALUE
+                ),
+            });
+        }
+        Ok(())
+    }
+
+    // Retry until the host acknowledges, returning the number of attempts used
+    pub fn send(&self) -> Result<u32, SystemError> {
+        let mut attempt = 1;
+        loop {
+            match self.send_once() {
+                Ok(()) => {
+                    info!(attempt = attempt; "Heartbeat acknowledged");
+                    return Ok(attempt);
+                }
+                Err(e) if attempt >= self.attempts => {
+                    return Err(SystemError {
+                        message: format!("Heartbeat failed after {} attempts: {}", attempt, e.message),
+                    });
+                }
+                Err(e) => {
+                    warn!(attempt = attempt; "Heartbeat failed: {}", e.message);
+                    thread::sleep(self.retry_delay);
+                    attempt += 1;
+                }
+            }
+        }
+    }
+}
+
+fn set_timeouts(stream: &File, timeout: Duration) -> Result<(), SystemError> {
+    use libc::{setsockopt, timeval, SOL_SOCKET, SO_RCVTIMEO, SO_SNDTIMEO};
+    let tv = timeval {
+        tv_sec: timeout.as_secs() as _,
+        tv_usec: timeout.subsec_micros() as _,
+    };
+    for option in [SO_RCVTIMEO, SO_SNDTIMEO] {
+        let result = unsafe {
+            setsockopt(
+                stream.as_raw_fd(),
+                SOL_SOCKET,
+                option,
+                &tv as *const _ as *const libc::c_void,
+                std::mem::size_of::<timeval>() as _,
+            )
+        };
+        if result < 0 {
+            return Err(SystemError {
+                message: format!(
+                    "Failed to set socket timeout: {}",
+                    std::io::Error::last_os_error()
+                ),
+            });
+        }
+    }
+    Ok(())
+}
+
+// Host side stand-in for the hypervisor listener: echo every heartbeat
+// received on cid:port. Serves `count` connections, or forever if None.
+pub fn serve(cid: u32, port: u32, count: Option<usize>) -> Result<(), SystemError> {
+    use libc::{accept, AF_VSOCK};
+    let listener = unsafe { File::from_raw_fd(socket_listen(AF_VSOCK, port, cid)?) };
+    let mut served = 0;
+    while count.map_or(true, |count| served < count) {
+        let fd = unsafe { accept(listener.as_raw_fd(), std::ptr::null_mut(), std::ptr::null_mut()) };
+        if fd < 0 {
+            return Err(SystemError {
+                message: format!(
+                    "Failed to accept heartbeat: {}",
+                    std::io::Error::last_os_error()
+                ),
+            });
+        }
+        let mut stream = unsafe { File::from_raw_fd(fd) };
+        let mut buf = [0u8; 1];
+        match stream.read_exact(&mut buf).and_then(|()| stream.write_all(&buf)) {
+            Ok(()) => info!("Echoed heartbeat {:#04x}", buf[0]),
+            Err(e) => warn!("Heartbeat connection failed: {}", e),
+        }
+        served += 1;
+    }
+    Ok(())
+}
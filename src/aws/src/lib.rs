@@ -1,65 +1,193 @@
-use system::{dmesg, SystemError};
+use system::module::Module;
+use system::SystemError;
+
+// Character device created by the NSM driver
+pub const NSM_DEVICE: &str = "/dev/nsm";
+
+pub mod heartbeat;
+pub mod mock;
 
 // Signal to Nitro hypervisor that booting was successful
-fn nitro_heartbeat() {
-    use system::socket_connect;
-    use libc::{write, read, close, AF_VSOCK};
-    let mut buf: [u8; 1] = [0; 1];
-    buf[0] = 0xB7; // AWS Nitro heartbeat value
-    let fd = match socket_connect(AF_VSOCK, 9000, 3) {
-        Ok(f)=> f,
-        Err(e)=> {
-            eprintln!("{}", e);
-            return
-        },
-    };
-    unsafe {
-        write(fd, buf.as_ptr() as _, 1);
-        read(fd, buf.as_ptr() as _, 1);
-        close(fd);
+pub fn nitro_heartbeat() -> Result<u32, SystemError> {
+    heartbeat::Heartbeat::default().send()
+}
+
+// Randomness from the Nitro device over a single long lived NSM handle
+pub struct NsmEntropySource {
+    // None when the mock NSM is enabled
+    fd: Option<i32>,
+}
+
+impl NsmEntropySource {
+    pub fn open() -> Result<Self, SystemError> {
+        use nsm_lib::nsm_lib_init;
+        if mock::enabled() {
+            return Ok(NsmEntropySource { fd: None });
+        }
+        let fd = nsm_lib_init();
+        if fd < 0 {
+            return Err(SystemError {
+                message: String::from("Failed to connect to NSM device")
+            });
+        }
+        Ok(NsmEntropySource { fd: Some(fd) })
+    }
+
+    // Fill dest completely, requesting as many NSM samples as needed
+    pub fn fill(&mut self, dest: &mut [u8]) -> Result<(), SystemError> {
+        use nsm_api::api::ErrorCode;
+        use nsm_lib::nsm_get_random;
+        let Some(fd) = self.fd else {
+            return mock::random(dest);
+        };
+        let mut filled = 0;
+        while filled < dest.len() {
+            let mut buf = [0u8; 256];
+            let mut buf_len = buf.len();
+            let status = unsafe {
+                nsm_get_random(fd, buf.as_mut_ptr(), &mut buf_len)
+            };
+            match status {
+                ErrorCode::Success if buf_len > 0 && buf_len <= buf.len() => {
+                    let take = buf_len.min(dest.len() - filled);
+                    dest[filled..filled + take].copy_from_slice(&buf[..take]);
+                    filled += take;
+                },
+                ErrorCode::Success => {
+                    return Err(SystemError {
+                        message: format!("NSM returned invalid entropy length: {}", buf_len)
+                    });
+                },
+                _ => {
+                    return Err(SystemError {
+                        message: format!("Failed to get entropy from NSM device: {:?}", status)
+                    });
+                }
+            };
+        }
+        Ok(())
+    }
+
+    pub fn sample(&mut self, size: usize) -> Result<Vec<u8>, SystemError> {
+        let mut dest = vec![0u8; size];
+        self.fill(&mut dest)?;
+        Ok(dest)
+    }
+}
+
+impl Drop for NsmEntropySource {
+    fn drop(&mut self) {
+        if let Some(fd) = self.fd {
+            nsm_lib::nsm_lib_exit(fd);
+        }
+    }
+}
+
+impl rand_core::RngCore for NsmEntropySource {
+    fn next_u32(&mut self) -> u32 {
+        rand_core::impls::next_u32_via_fill(self)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        rand_core::impls::next_u64_via_fill(self)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        if let Err(e) = self.fill(dest) {
+            panic!("{}", e.message);
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+        self.fill(dest).map_err(rand_core::Error::new)
     }
-    dmesg(format!("Sent NSM heartbeat"));
 }
 
+impl rand_core::CryptoRng for NsmEntropySource {}
+
 // Get entropy sample from Nitro device
 pub fn get_entropy(size: usize) -> Result<Vec<u8>, SystemError> {
-    use nsm_api::api::ErrorCode;
-    use nsm_lib::{nsm_get_random, nsm_lib_init};
-    let nsm_fd = nsm_lib_init();
-    if nsm_fd < 0 {
-        return Err(SystemError {
-            message: String::from("Failed to connect to NSM device")
-        });
-    };
-    let mut dest = Vec::with_capacity(size);
-    while dest.len() < size {
-        let mut buf = [0u8; 256];
-        let mut buf_len = buf.len();
-        let status = unsafe {
-            nsm_get_random(nsm_fd, buf.as_mut_ptr(), &mut buf_len)
-        };
-        match status {
-            ErrorCode::Success => {
-                dest.extend_from_slice(&buf);
-            },
-            _ => {
-                return Err(SystemError {
-                    message: String::from("Failed to get entropy from NSM device")
-                });
-            }
+    NsmEntropySource::open()?.sample(size)
+}
+
+// Send a single request to the Nitro device over a fresh NSM handle
+fn nsm_request(request: nsm_api::api::Request) -> Result<nsm_api::api::Response, SystemError> {
+    use nsm_api::api::Response;
+    use nsm_api::driver::{nsm_exit, nsm_init, nsm_process_request};
+    let response = if mock::enabled() {
+        mock::process(request)
+    } else {
+        let nsm_fd = nsm_init();
+        if nsm_fd < 0 {
+            return Err(SystemError {
+                message: String::from("Failed to connect to NSM device")
+            });
         };
+        let response = nsm_process_request(nsm_fd, request);
+        nsm_exit(nsm_fd);
+        response
+    };
+    match response {
+        Response::Error(code) => Err(SystemError {
+            message: format!("NSM request failed: {:?}", code)
+        }),
+        response => Ok(response),
     }
-    Ok(dest)
 }
 
-// Initialize nitro device
-pub fn init_platform(){
-    use system::insmod;
-    // TODO: error handling
-    nitro_heartbeat();
-
-	match insmod("/nsm.ko") {
-        Ok(())=> dmesg(format!("Loaded nsm.ko")),
-        Err(e)=> eprintln!("{}", e)
+// Request a signed attestation document from the Nitro device
+pub fn attestation_document(
+    user_data: Option<Vec<u8>>,
+    nonce: Option<Vec<u8>>,
+    public_key: Option<Vec<u8>>,
+) -> Result<Vec<u8>, SystemError> {
+    use nsm_api::api::{Request, Response};
+    use serde_bytes::ByteBuf;
+    let request = Request::Attestation {
+        user_data: user_data.map(ByteBuf::from),
+        nonce: nonce.map(ByteBuf::from),
+        public_key: public_key.map(ByteBuf::from),
     };
+    match nsm_request(request)? {
+        Response::Attestation { document } => Ok(document),
+        _ => Err(SystemError {
+            message: String::from("Unexpected NSM response to attestation request")
+        }),
+    }
+}
+
+// Extend a PCR with data, returning the new PCR value
+pub fn extend_pcr(index: u16, data: Vec<u8>) -> Result<Vec<u8>, SystemError> {
+    use nsm_api::api::{Request, Response};
+    match nsm_request(Request::ExtendPCR { index, data })? {
+        Response::ExtendPCR { data } => Ok(data),
+        _ => Err(SystemError {
+            message: String::from("Unexpected NSM response to PCR extend request")
+        }),
+    }
+}
+
+// Only root may open the NSM device. Services reach it through the
+// broker in init.
+fn restrict_device() -> Result<(), SystemError> {
+    use std::os::unix::fs::PermissionsExt;
+    std::os::unix::fs::chown(NSM_DEVICE, Some(0), Some(0))
+        .and_then(|()| {
+            std::fs::set_permissions(NSM_DEVICE, std::fs::Permissions::from_mode(0o600))
+        })
+        .map_err(|e| SystemError {
+            message: format!("Failed to restrict {}: {}", NSM_DEVICE, e)
+        })
+}
+
+// Initialize nitro device: load the manifest's modules, which include the
+// NSM driver, and check that the device node appeared
+pub fn init_platform(modules: &[Module]) -> Result<(), SystemError> {
+    system::module::load_all(modules)?;
+    if !std::path::Path::new(NSM_DEVICE).exists() {
+        return Err(SystemError {
+            message: format!("{} missing after loading modules", NSM_DEVICE)
+        });
+    }
+    restrict_device()
 }
@@ -0,0 +1,151 @@
+use nsm_api::api::{Digest, ErrorCode, Request, Response};
+use serde_bytes::ByteBuf;
+use serde_cbor::Value;
+use sha2::{Digest as _, Sha384};
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use system::SystemError;
+
+// In-process stand-in for the NSM device, used when init is emulated on a
+// development machine. Attestation documents have the layout of real ones
+// but an all-zero signature and no certificate chain, so nothing that
+// verifies them will accept them.
+
+const PCR_COUNT: usize = 32;
+const PCR_LEN: usize = 48;
+// PCRs 0-15 belong to the image and the hypervisor and are read only
+const FIRST_WRITABLE_PCR: usize = 16;
+const RANDOM_LEN: usize = 256;
+const MODULE_ID: &str = "i-00000000000000000-enc0000000000000000";
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+struct Pcrs {
+    values: [[u8; PCR_LEN]; PCR_COUNT],
+    locked: BTreeSet<u16>,
+}
+
+static PCRS: Mutex<Pcrs> = Mutex::new(Pcrs {
+    values: [[0; PCR_LEN]; PCR_COUNT],
+    locked: BTreeSet::new(),
+});
+
+// Route every NSM request of this process to the mock
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+pub(crate) fn random(dest: &mut [u8]) -> Result<(), SystemError> {
+    let mut filled = 0;
+    while filled < dest.len() {
+        let rest = &mut dest[filled..];
+        let read = unsafe { libc::getrandom(rest.as_mut_ptr() as _, rest.len(), 0) };
+        if read < 0 {
+            return Err(SystemError {
+                message: format!("getrandom failed: {}", std::io::Error::last_os_error()),
+            });
+        }
+        filled += read as usize;
+    }
+    Ok(())
+}
+
+fn bytes(data: &[u8]) -> Value {
+    Value::Bytes(data.to_vec())
+}
+
+fn text(value: &str) -> Value {
+    Value::Text(value.to_string())
+}
+
+// COSE_Sign1 [protected, unprotected, payload, signature] around the
+// attestation document, as the device returns it
+fn attestation(
+    pcrs: &Pcrs,
+    user_data: Option<ByteBuf>,
+    nonce: Option<ByteBuf>,
+    public_key: Option<ByteBuf>,
+) -> Vec<u8> {
+    let optional = |value: Option<ByteBuf>| value.map(|v| bytes(&v)).unwrap_or(Value::Null);
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    let mut document = BTreeMap::new();
+    document.insert(text("module_id"), text(MODULE_ID));
+    document.insert(text("digest"), text("SHA384"));
+    document.insert(text("timestamp"), Value::Integer(timestamp.as_millis() as i128));
+    let values = pcrs.values.iter().enumerate();
+    let values = values.map(|(index, value)| (Value::Integer(index as i128), bytes(value)));
+    document.insert(text("pcrs"), Value::Map(values.collect()));
+    document.insert(text("certificate"), bytes(&[]));
+    document.insert(text("cabundle"), Value::Array(Vec::new()));
+    document.insert(text("public_key"), optional(public_key));
+    document.insert(text("user_data"), optional(user_data));
+    document.insert(text("nonce"), optional(nonce));
+    let payload = serde_cbor::to_vec(&Value::Map(document)).expect("document serializes");
+    // Algorithm ES384
+    let protected = BTreeMap::from([(Value::Integer(1), Value::Integer(-35))]);
+    let protected = serde_cbor::to_vec(&Value::Map(protected)).expect("header serializes");
+    let sign1 = Value::Array(vec![
+        bytes(&protected),
+        Value::Map(BTreeMap::new()),
+        bytes(&payload),
+        bytes(&[0; 96]),
+    ]);
+    serde_cbor::to_vec(&sign1).expect("COSE_Sign1 serializes")
+}
+
+pub fn process(request: Request) -> Response {
+    let mut pcrs = PCRS.lock().expect("!lock");
+    let pcr = |index: u16| (index as usize) < PCR_COUNT;
+    match request {
+        Request::DescribePCR { index } if pcr(index) => Response::DescribePCR {
+            lock: index < FIRST_WRITABLE_PCR as u16 || pcrs.locked.contains(&index),
+            data: pcrs.values[index as usize].to_vec(),
+        },
+        Request::ExtendPCR { index, .. } if !pcr(index) => Response::Error(ErrorCode::InvalidIndex),
+        Request::ExtendPCR { index, .. }
+            if index < FIRST_WRITABLE_PCR as u16 || pcrs.locked.contains(&index) =>
+        {
+            Response::Error(ErrorCode::ReadOnlyIndex)
+        }
+        Request::ExtendPCR { index, data } => {
+            let value = &mut pcrs.values[index as usize];
+            let extended = Sha384::new().chain_update(*value).chain_update(&data).finalize();
+            value.copy_from_slice(&extended);
+            Response::ExtendPCR { data: value.to_vec() }
+        }
+        Request::LockPCR { index } if pcr(index) => {
+            pcrs.locked.insert(index);
+            Response::LockPCR
+        }
+        Request::LockPCRs { range } if range as usize <= PCR_COUNT => {
+            pcrs.locked.extend(0..range);
+            Response::LockPCRs
+        }
+        Request::DescribeNSM => Response::DescribeNSM {
+            version_major: 1,
+            version_minor: 0,
+            version_patch: 0,
+            module_id: MODULE_ID.to_string(),
+            max_pcrs: PCR_COUNT as u16,
+            locked_pcrs: (0..FIRST_WRITABLE_PCR as u16).chain(pcrs.locked.iter().copied()).collect(),
+            digest: Digest::SHA384,
+        },
+        Request::Attestation { user_data, nonce, public_key } => Response::Attestation {
+            document: attestation(&pcrs, user_data, nonce, public_key),
+        },
+        Request::GetRandom => {
+            let mut buf = vec![0u8; RANDOM_LEN];
+            match random(&mut buf) {
+                Ok(()) => Response::GetRandom { random: buf },
+                Err(_) => Response::Error(ErrorCode::InternalError),
+            }
+        }
+        _ => Response::Error(ErrorCode::InvalidIndex),
+    }
+}
@@ -0,0 +1,377 @@
+use sha2::{Digest, Sha384};
+use std::fmt;
+use std::ops::Range;
+use system::SystemError;
+
+// Enclave Image Format as written by eif_build: a big-endian header with a
+// table of up to 32 sections, each of which starts with its own header.
+// PCRs are recomputed the way eif_build measures the image, so they can be
+// checked against nitro.pcrs and attestation documents without the
+// Nitro tooling.
+
+pub const MAGIC: &[u8; 4] = b".eif";
+const MAX_SECTIONS: usize = 32;
+// magic, version, flags, default memory and CPUs, reserved, section count,
+// section offsets and sizes, unused, CRC32
+const HEADER_LEN: usize = 4 + 2 + 2 + 8 + 8 + 2 + 2 + 8 * MAX_SECTIONS * 2 + 4 + 4;
+// type, flags, size
+const SECTION_HEADER_LEN: usize = 2 + 2 + 8;
+// Header flag of images built for aarch64
+const ARCH_ARM64: u16 = 1;
+pub const PCR_LEN: usize = 48;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SectionKind {
+    Kernel,
+    Cmdline,
+    Ramdisk,
+    Signature,
+    Metadata,
+    Unknown(u16),
+}
+
+impl SectionKind {
+    fn from_u16(kind: u16) -> SectionKind {
+        match kind {
+            1 => SectionKind::Kernel,
+            2 => SectionKind::Cmdline,
+            3 => SectionKind::Ramdisk,
+            4 => SectionKind::Signature,
+            5 => SectionKind::Metadata,
+            other => SectionKind::Unknown(other),
+        }
+    }
+}
+
+impl fmt::Display for SectionKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SectionKind::Kernel => f.pad("kernel"),
+            SectionKind::Cmdline => f.pad("cmdline"),
+            SectionKind::Ramdisk => f.pad("ramdisk"),
+            SectionKind::Signature => f.pad("signature"),
+            SectionKind::Metadata => f.pad("metadata"),
+            SectionKind::Unknown(kind) => f.pad(&format!("unknown({})", kind)),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Header {
+    pub version: u16,
+    pub flags: u16,
+    pub default_memory: u64,
+    pub default_cpus: u64,
+    pub section_count: u16,
+    pub crc32: u32,
+}
+
+impl Header {
+    pub fn arch(&self) -> &'static str {
+        if self.flags & ARCH_ARM64 != 0 {
+            "aarch64"
+        } else {
+            "x86_64"
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Section {
+    pub kind: SectionKind,
+    pub flags: u16,
+    // Offset of the section header in the image
+    pub offset: u64,
+    // Section data in the image, without its header
+    range: Range<usize>,
+}
+
+impl Section {
+    pub fn size(&self) -> usize {
+        self.range.len()
+    }
+}
+
+// PCR0 measures the whole image, PCR1 the kernel and boot ramdisk (the
+// first one) and PCR2 the application ramdisks (all the others)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Pcrs {
+    pub pcr0: [u8; PCR_LEN],
+    pub pcr1: [u8; PCR_LEN],
+    pub pcr2: [u8; PCR_LEN],
+}
+
+impl Pcrs {
+    pub fn iter(&self) -> impl Iterator<Item = (&'static str, &[u8; PCR_LEN])> {
+        [("PCR0", &self.pcr0), ("PCR1", &self.pcr1), ("PCR2", &self.pcr2)].into_iter()
+    }
+
+    // The JSON eif_build writes with --pcrs_output
+    pub fn to_json(&self) -> String {
+        let mut measurements = serde_json::Map::new();
+        measurements.insert("HashAlgorithm".to_string(), "Sha384 { ... }".into());
+        for (name, value) in self.iter() {
+            measurements.insert(name.to_string(), hex(value).into());
+        }
+        serde_json::Value::Object(measurements).to_string()
+    }
+}
+
+// Measurement register as the hypervisor computes it: a SHA-384 of the
+// measured data, extended into a register that starts as zeros
+struct Measurement(Sha384);
+
+impl Measurement {
+    fn new() -> Self {
+        Measurement(Sha384::new())
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    fn finalize(self) -> [u8; PCR_LEN] {
+        let digest = self.0.finalize();
+        Sha384::new().chain_update([0u8; PCR_LEN]).chain_update(digest).finalize().into()
+    }
+}
+
+pub struct Eif {
+    pub header: Header,
+    pub sections: Vec<Section>,
+    bytes: Vec<u8>,
+}
+
+fn invalid(message: String) -> SystemError {
+    SystemError { message: format!("Invalid EIF: {}", message) }
+}
+
+fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes(bytes[offset..offset + 2].try_into().expect("2 bytes"))
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(bytes[offset..offset + 4].try_into().expect("4 bytes"))
+}
+
+fn u64_at(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_be_bytes(bytes[offset..offset + 8].try_into().expect("8 bytes"))
+}
+
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+impl Eif {
+    pub fn load(path: &str) -> Result<Eif, SystemError> {
+        let bytes = std::fs::read(path).map_err(|e| SystemError {
+            message: format!("Failed to read {}: {}", path, e),
+        })?;
+        Eif::parse(bytes).map_err(|e| SystemError { message: format!("{}: {}", path, e.message) })
+    }
+
+    pub fn parse(bytes: Vec<u8>) -> Result<Eif, SystemError> {
+        if bytes.len() < HEADER_LEN || &bytes[..4] != MAGIC {
+            return Err(invalid("no EIF header".to_string()));
+        }
+        let header = Header {
+            version: u16_at(&bytes, 4),
+            flags: u16_at(&bytes, 6),
+            default_memory: u64_at(&bytes, 8),
+            default_cpus: u64_at(&bytes, 16),
+            section_count: u16_at(&bytes, 26),
+            crc32: u32_at(&bytes, HEADER_LEN - 4),
+        };
+        let count = header.section_count as usize;
+        if count > MAX_SECTIONS {
+            return Err(invalid(format!("{} sections, at most {} allowed", count, MAX_SECTIONS)));
+        }
+        let offsets = 28;
+        let sizes = offsets + 8 * MAX_SECTIONS;
+        let mut sections = Vec::with_capacity(count);
+        for index in 0..count {
+            let offset = u64_at(&bytes, offsets + 8 * index);
+            let size = u64_at(&bytes, sizes + 8 * index);
+            let start = usize::try_from(offset)
+                .ok()
+                .and_then(|offset| offset.checked_add(SECTION_HEADER_LEN))
+                .filter(|start| *start <= bytes.len())
+                .ok_or_else(|| invalid(format!("section {} starts past the end", index)))?;
+            let end = usize::try_from(size)
+                .ok()
+                .and_then(|size| start.checked_add(size))
+                .filter(|end| *end <= bytes.len())
+                .ok_or_else(|| invalid(format!("section {} ends past the end", index)))?;
+            let section_header = &bytes[start - SECTION_HEADER_LEN..start];
+            if u64_at(section_header, 4) != size {
+                return Err(invalid(format!("section {} sizes disagree", index)));
+            }
+            sections.push(Section {
+                kind: SectionKind::from_u16(u16_at(section_header, 0)),
+                flags: u16_at(section_header, 2),
+                offset,
+                range: start..end,
+            });
+        }
+        Ok(Eif { header, sections, bytes })
+    }
+
+    pub fn data(&self, section: &Section) -> &[u8] {
+        &self.bytes[section.range.clone()]
+    }
+
+    pub fn sections_of(&self, kind: SectionKind) -> impl Iterator<Item = &Section> {
+        self.sections.iter().filter(move |section| section.kind == kind)
+    }
+
+    // Kernel command line, without the terminating NULs eif_build may add
+    pub fn cmdline(&self) -> Option<String> {
+        let section = self.sections_of(SectionKind::Cmdline).next()?;
+        let data = self.data(section);
+        Some(String::from_utf8_lossy(data).trim_end_matches('\0').to_string())
+    }
+
+    // CRC32 over the header without its CRC field, then every section
+    // header and data, as eif_build computes it
+    pub fn crc32(&self) -> u32 {
+        let mut crc = crc32fast::Hasher::new();
+        crc.update(&self.bytes[..HEADER_LEN - 4]);
+        for section in &self.sections {
+            crc.update(&self.bytes[section.range.start - SECTION_HEADER_LEN..section.range.end]);
+        }
+        crc.finalize()
+    }
+
+    pub fn pcrs(&self) -> Pcrs {
+        let (mut image, mut boot, mut application) =
+            (Measurement::new(), Measurement::new(), Measurement::new());
+        let mut ramdisks = 0;
+        for section in &self.sections {
+            let data = self.data(section);
+            match section.kind {
+                SectionKind::Kernel | SectionKind::Cmdline => {
+                    image.update(data);
+                    boot.update(data);
+                }
+                SectionKind::Ramdisk => {
+                    image.update(data);
+                    if ramdisks == 0 {
+                        boot.update(data);
+                    } else {
+                        application.update(data);
+                    }
+                    ramdisks += 1;
+                }
+                _ => {}
+            }
+        }
+        Pcrs { pcr0: image.finalize(), pcr1: boot.finalize(), pcr2: application.finalize() }
+    }
+
+    // SHA-384 of a section's data, to tell which sections two images
+    // differ in
+    pub fn digest(&self, section: &Section) -> String {
+        hex(&Sha384::digest(self.data(section)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KERNEL: &[u8] = b"kernel";
+    const CMDLINE: &[u8] = b"console=ttyS0\0";
+    const BOOT: &[u8] = b"boot ramdisk";
+    const APPLICATION: &[u8] = b"application ramdisk";
+    // PCR2 with no application ramdisk: nothing was measured
+    const EMPTY: &str = "21b9efbc184807662e966d34f390821309eeac6802309798826296bf3e8bec7c10edb30948c90ba67310f7b964fc500a";
+
+    // Image with the given sections laid out after the header, as
+    // eif_build writes them
+    fn image(sections: &[(u16, &[u8])]) -> Vec<u8> {
+        let mut bytes = vec![0u8; HEADER_LEN];
+        bytes[..4].copy_from_slice(MAGIC);
+        bytes[4..6].copy_from_slice(&4u16.to_be_bytes());
+        bytes[26..28].copy_from_slice(&(sections.len() as u16).to_be_bytes());
+        for (index, (kind, data)) in sections.iter().enumerate() {
+            let offset = 28 + 8 * index;
+            let size = offset + 8 * MAX_SECTIONS;
+            let start = bytes.len() as u64;
+            bytes[offset..offset + 8].copy_from_slice(&start.to_be_bytes());
+            bytes[size..size + 8].copy_from_slice(&(data.len() as u64).to_be_bytes());
+            bytes.extend_from_slice(&kind.to_be_bytes());
+            bytes.extend_from_slice(&0u16.to_be_bytes());
+            bytes.extend_from_slice(&(data.len() as u64).to_be_bytes());
+            bytes.extend_from_slice(data);
+        }
+        bytes
+    }
+
+    fn error(bytes: Vec<u8>) -> String {
+        match Eif::parse(bytes) {
+            Ok(_) => panic!("parsed an invalid image"),
+            Err(e) => e.message,
+        }
+    }
+
+    #[test]
+    fn known_answer_pcrs() {
+        let eif = Eif::parse(image(&[(1, KERNEL), (2, CMDLINE), (3, BOOT), (3, APPLICATION)])).unwrap();
+        let kinds: Vec<SectionKind> = eif.sections.iter().map(|section| section.kind).collect();
+        use SectionKind::*;
+        assert_eq!(kinds, [Kernel, Cmdline, Ramdisk, Ramdisk]);
+        assert_eq!(eif.cmdline().as_deref(), Some("console=ttyS0"));
+        let pcrs = eif.pcrs();
+        // sha384(zeros || sha384(data)), computed independently
+        assert_eq!(
+            hex(&pcrs.pcr0),
+            "496c530564d303d3b51ad74303f693ed2fcf2cf27ff6eb4a20ccc334c1cd2659d0ae89bae86c4c8a98710a37f38435e2"
+        );
+        assert_eq!(
+            hex(&pcrs.pcr1),
+            "fd83a8483f97895f0e13e326f890e17de1e43b06c57cfca6fa91a104b4ddf197629666e1aabfbc34e9557f5a00e620ca"
+        );
+        assert_eq!(
+            hex(&pcrs.pcr2),
+            "15ceb42332f9052bf5f8501d40a138e9439a594abb5f342a74da495ce850c4bbfc3e97487c29122e4193cea8e6a2816c"
+        );
+    }
+
+    #[test]
+    fn single_ramdisk_leaves_pcr2_empty() {
+        let eif = Eif::parse(image(&[(1, KERNEL), (2, CMDLINE), (3, BOOT)])).unwrap();
+        let pcrs = eif.pcrs();
+        assert_eq!(pcrs.pcr0, pcrs.pcr1);
+        assert_eq!(hex(&pcrs.pcr2), EMPTY);
+    }
+
+    #[test]
+    fn rejects_bad_magic() {
+        let mut bytes = image(&[(1, KERNEL)]);
+        bytes[..4].copy_from_slice(b"ELF\x7f");
+        assert!(error(bytes).contains("no EIF header"));
+        assert!(error(MAGIC.to_vec()).contains("no EIF header"));
+    }
+
+    #[test]
+    fn rejects_truncated_sections() {
+        let bytes = image(&[(1, KERNEL), (2, CMDLINE)]);
+        // Cut inside the second section header
+        let truncated = bytes[..bytes.len() - CMDLINE.len() - 4].to_vec();
+        let message = error(truncated);
+        assert!(message.contains("section 1 starts past the end"), "{}", message);
+        // Cut inside the second section's data
+        let truncated = bytes[..bytes.len() - 1].to_vec();
+        let message = error(truncated);
+        assert!(message.contains("section 1 ends past the end"), "{}", message);
+    }
+
+    #[test]
+    fn rejects_disagreeing_sizes() {
+        let mut bytes = image(&[(1, KERNEL)]);
+        // Section header says one byte more than the header table
+        let size = HEADER_LEN + 4;
+        bytes[size + 7] += 1;
+        assert!(error(bytes).contains("sizes disagree"));
+    }
+}
@@ -0,0 +1,176 @@
+use eif::{hex, Eif, SectionKind};
+use std::collections::BTreeMap;
+use system::{error, SystemError};
+
+const USAGE: &str = "Usage: eif describe <eif> | pcrs <eif> | diff <eif> <eif> | verify <eif> <pcrs>";
+
+fn describe(eif: &Eif) {
+    let header = &eif.header;
+    let crc = eif.crc32();
+    println!("version        {}", header.version);
+    println!("arch           {}", header.arch());
+    println!("default memory {} MiB", header.default_memory >> 20);
+    println!("default cpus   {}", header.default_cpus);
+    if crc == header.crc32 {
+        println!("crc32          {:08x} ok", crc);
+    } else {
+        println!("crc32          {:08x}, header says {:08x}", crc, header.crc32);
+    }
+    println!("sections");
+    for (index, section) in eif.sections.iter().enumerate() {
+        println!(
+            "  {:2} {:<9} offset {:>10} size {:>10} sha384 {}",
+            index,
+            section.kind,
+            section.offset,
+            section.size(),
+            eif.digest(section)
+        );
+    }
+    if let Some(cmdline) = eif.cmdline() {
+        println!("cmdline        {}", cmdline);
+    }
+    for section in eif.sections_of(SectionKind::Metadata) {
+        match serde_json::from_slice::<serde_json::Value>(eif.data(section)) {
+            Ok(metadata) => println!("metadata       {}", metadata),
+            Err(_) => println!("metadata       not JSON"),
+        }
+    }
+    if eif.sections_of(SectionKind::Signature).next().is_some() {
+        println!("signed         yes, PCR8 is not recomputed");
+    }
+    for (name, value) in eif.pcrs().iter() {
+        println!("{:<14} {}", name, hex(value));
+    }
+}
+
+// Print every difference, returning whether there were any
+fn diff(a: &Eif, b: &Eif) -> bool {
+    let mut differences = Vec::new();
+    let mut compare = |what: String, a: String, b: String| {
+        if a != b {
+            differences.push(format!("{}: {} != {}", what, a, b));
+        }
+    };
+    let (x, y) = (&a.header, &b.header);
+    compare("version".to_string(), x.version.to_string(), y.version.to_string());
+    compare("arch".to_string(), x.arch().to_string(), y.arch().to_string());
+    compare("default memory".to_string(), x.default_memory.to_string(), y.default_memory.to_string());
+    compare("default cpus".to_string(), x.default_cpus.to_string(), y.default_cpus.to_string());
+    compare("sections".to_string(), a.sections.len().to_string(), b.sections.len().to_string());
+    for (index, (x, y)) in a.sections.iter().zip(&b.sections).enumerate() {
+        let what = |field: &str| format!("section {} {}", index, field);
+        compare(what("type"), x.kind.to_string(), y.kind.to_string());
+        compare(what("size"), x.size().to_string(), y.size().to_string());
+        compare(what("sha384"), a.digest(x), b.digest(y));
+    }
+    compare(
+        "cmdline".to_string(),
+        format!("{:?}", a.cmdline().unwrap_or_default()),
+        format!("{:?}", b.cmdline().unwrap_or_default()),
+    );
+    for ((name, x), (_, y)) in a.pcrs().iter().zip(b.pcrs().iter()) {
+        compare(name.to_string(), hex(x), hex(y));
+    }
+    for difference in &differences {
+        println!("{}", difference);
+    }
+    !differences.is_empty()
+}
+
+// Check the recomputed PCRs against a nitro.pcrs file, returning whether
+// they all match
+fn verify(eif: &Eif, path: &str) -> Result<bool, SystemError> {
+    let text = std::fs::read_to_string(path).map_err(|e| SystemError {
+        message: format!("Failed to read {}: {}", path, e),
+    })?;
+    let expected: BTreeMap<String, String> = serde_json::from_str(&text).map_err(|e| SystemError {
+        message: format!("Failed to parse {}: {}", path, e),
+    })?;
+    let pcrs = eif.pcrs();
+    let mut matched = true;
+    for (name, value) in pcrs.iter() {
+        let value = hex(value);
+        match expected.get(name) {
+            Some(expected) if expected.eq_ignore_ascii_case(&value) => println!("{} ok", name),
+            Some(expected) => {
+                println!("{} mismatch: computed {}, expected {}", name, value, expected);
+                matched = false;
+            }
+            None => {
+                println!("{} not in {}, computed {}", name, path, value);
+                matched = false;
+            }
+        }
+    }
+    for name in expected.keys().filter(|name| name.starts_with("PCR")) {
+        if !pcrs.iter().any(|(pcr, _)| pcr == name) {
+            println!("{} not recomputed", name);
+        }
+    }
+    Ok(matched)
+}
+
+// Exit status 0 when images match or verify, 1 when they do not and 2 on
+// errors, like diff(1)
+fn run(args: &[String]) -> Result<bool, SystemError> {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    match args.as_slice() {
+        ["describe", path] => {
+            describe(&Eif::load(path)?);
+            Ok(true)
+        }
+        ["pcrs", path] => {
+            println!("{}", Eif::load(path)?.pcrs().to_json());
+            Ok(true)
+        }
+        ["diff", a, b] => Ok(!diff(&Eif::load(a)?, &Eif::load(b)?)),
+        ["verify", path, pcrs] => verify(&Eif::load(path)?, pcrs),
+        _ => Err(SystemError { message: USAGE.to_string() }),
+    }
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match run(&args) {
+        Ok(true) => {}
+        Ok(false) => std::process::exit(1),
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(2);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Image without sections, so every PCR measures nothing
+    fn empty_eif() -> Eif {
+        let mut bytes = vec![0u8; 548];
+        bytes[..4].copy_from_slice(eif::MAGIC);
+        Eif::parse(bytes).map_err(|e| e.message).unwrap()
+    }
+
+    fn verify_with(name: &str, pcrs: &BTreeMap<&str, String>) -> bool {
+        let path = std::env::temp_dir().join(format!("eif-{}-{}.pcrs", name, std::process::id()));
+        std::fs::write(&path, serde_json::to_string(pcrs).unwrap()).unwrap();
+        let matched = verify(&empty_eif(), &path.to_string_lossy()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        matched
+    }
+
+    #[test]
+    fn verify_requires_every_pcr() {
+        let eif = empty_eif();
+        let mut pcrs: BTreeMap<&str, String> =
+            eif.pcrs().iter().map(|(name, value)| (name, hex(value).to_uppercase())).collect();
+        pcrs.insert("HashAlgorithm", "Sha384 { ... }".to_string());
+        assert!(verify_with("all", &pcrs));
+        pcrs.remove("PCR2");
+        assert!(!verify_with("missing", &pcrs));
+        pcrs.insert("PCR2", "00".repeat(48));
+        assert!(!verify_with("mismatch", &pcrs));
+    }
+}
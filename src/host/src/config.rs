@@ -0,0 +1,104 @@
+use serde::Deserialize;
+use system::log::Level;
+use system::SystemError;
+
+// Default location of the host configuration
+pub const CONFIG_PATH: &str = "/etc/enclave-host.toml";
+
+// Host half of the enclave. Every section but `cid` is optional and the
+// component it configures only runs when it is present.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    // CID the enclave is started with (nitro-cli --enclave-cid)
+    pub cid: u32,
+    #[serde(default = "default_log_level", deserialize_with = "level")]
+    pub log_level: Level,
+    #[serde(default)]
+    pub inbound: Vec<Inbound>,
+    pub egress: Option<Egress>,
+    pub heartbeat: Option<Heartbeat>,
+    pub logs: Option<Logs>,
+    #[serde(default)]
+    pub outbound: Vec<Outbound>,
+    pub http_proxy: Option<HttpProxy>,
+}
+
+fn default_log_level() -> Level {
+    Level::Info
+}
+
+fn level<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Level, D::Error> {
+    let text = String::deserialize(deserializer)?;
+    text.parse().map_err(serde::de::Error::custom)
+}
+
+// Host TCP listener forwarded to a vsock port of the enclave
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Inbound {
+    // e.g. "0.0.0.0:80"
+    pub listen: String,
+    pub port: u32,
+}
+
+// Endpoint the enclave's tap0 connects to. Frames are exchanged with a
+// host TAP device; routing and NAT beyond it are left to the host.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Egress {
+    pub port: u32,
+    pub device: String,
+    // Address of the device, the enclave's default gateway and resolver
+    pub address: String,
+    pub netmask: String,
+    // Resolver DNS queries to the gateway are relayed to
+    pub dns_upstream: Option<String>,
+}
+
+// Answers the enclave's heartbeat when nitro-cli does not, e.g. for an
+// enclave started by other tooling
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Heartbeat {
+    pub port: u32,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Logs {
+    // File records are appended to
+    pub path: String,
+    // Name of a debug-mode enclave whose console is captured
+    pub console: Option<String>,
+    // vsock port accepting newline-delimited records
+    pub port: Option<u32>,
+}
+
+// vsock port whose connections are forwarded to one fixed remote, e.g. KMS
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Outbound {
+    pub port: u32,
+    // host:port
+    pub remote: String,
+}
+
+// HTTP CONNECT proxy on a vsock port, limited to the listed host:port pairs
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HttpProxy {
+    pub port: u32,
+    pub allow: Vec<String>,
+}
+
+impl Config {
+    pub fn load(path: &str) -> Result<Config, SystemError> {
+        let text = std::fs::read_to_string(path).map_err(|e| SystemError {
+            message: format!("Failed to read config {}: {}", path, e),
+        })?;
+        toml::from_str(&text).map_err(|e| SystemError {
+            message: format!("Failed to parse config {}: {}", path, e),
+        })
+    }
+}
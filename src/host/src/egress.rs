@@ -0,0 +1,207 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::net::{Ipv4Addr, UdpSocket};
+use std::os::fd::AsRawFd;
+use std::os::unix::net::UnixStream;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+use system::{info, warn, SystemError};
+use crate::config::Egress;
+use crate::vsock::Listener;
+
+// TUNSETIFF, _IOW('T', 202, int); missing from libc
+const TUNSETIFF: libc::c_ulong = 0x400454ca;
+// Frames are prefixed with their length as a little-endian u16, the
+// framing of the gvisor-tap-vsock transport the enclave's tap0 speaks
+const FRAME_LEN: usize = 0xffff;
+const DNS_TIMEOUT: Duration = Duration::from_secs(5);
+// How often a TAP reader checks whether its connection is over
+const STOP_POLL_MS: libc::c_int = 100;
+
+fn error(device: &str, what: &str, e: io::Error) -> SystemError {
+    SystemError { message: format!("Failed to {} {}: {}", what, device, e) }
+}
+
+fn ifreq(device: &str) -> libc::ifreq {
+    let mut request: libc::ifreq = unsafe { std::mem::zeroed() };
+    for (slot, byte) in request.ifr_name.iter_mut().zip(device.bytes().take(libc::IFNAMSIZ - 1)) {
+        *slot = byte as libc::c_char;
+    }
+    request
+}
+
+fn ioctl(fd: i32, request: libc::c_ulong, ifreq: &mut libc::ifreq) -> io::Result<()> {
+    match unsafe { libc::ioctl(fd, request, ifreq as *mut libc::ifreq) } {
+        result if result < 0 => Err(io::Error::last_os_error()),
+        _ => Ok(()),
+    }
+}
+
+fn sockaddr(address: Ipv4Addr) -> libc::sockaddr {
+    let mut sockaddr: libc::sockaddr_in = unsafe { std::mem::zeroed() };
+    sockaddr.sin_family = libc::AF_INET as libc::sa_family_t;
+    sockaddr.sin_addr.s_addr = u32::from(address).to_be();
+    unsafe { std::mem::transmute::<libc::sockaddr_in, libc::sockaddr>(sockaddr) }
+}
+
+fn parse_address(text: &str) -> Result<Ipv4Addr, SystemError> {
+    text.parse().map_err(|_| SystemError { message: format!("Invalid IPv4 address: {}", text) })
+}
+
+// Create the TAP device, assign its address and bring it up
+fn open_tap(config: &Egress) -> Result<File, SystemError> {
+    let device = config.device.as_str();
+    let tap = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/net/tun")
+        .map_err(|e| error(device, "open /dev/net/tun for", e))?;
+    let mut request = ifreq(device);
+    request.ifr_ifru.ifru_flags = (libc::IFF_TAP | libc::IFF_NO_PI) as libc::c_short;
+    ioctl(tap.as_raw_fd(), TUNSETIFF, &mut request).map_err(|e| error(device, "create", e))?;
+
+    let socket = UdpSocket::bind("0.0.0.0:0").map_err(|e| error(device, "configure", e))?;
+    let fd = socket.as_raw_fd();
+    let mut request = ifreq(device);
+    request.ifr_ifru.ifru_addr = sockaddr(parse_address(&config.address)?);
+    ioctl(fd, libc::SIOCSIFADDR, &mut request).map_err(|e| error(device, "set the address of", e))?;
+    let mut request = ifreq(device);
+    request.ifr_ifru.ifru_netmask = sockaddr(parse_address(&config.netmask)?);
+    ioctl(fd, libc::SIOCSIFNETMASK, &mut request).map_err(|e| error(device, "set the netmask of", e))?;
+    let mut request = ifreq(device);
+    ioctl(fd, libc::SIOCGIFFLAGS, &mut request).map_err(|e| error(device, "read flags of", e))?;
+    unsafe { request.ifr_ifru.ifru_flags |= libc::IFF_UP as libc::c_short };
+    ioctl(fd, libc::SIOCSIFFLAGS, &mut request).map_err(|e| error(device, "bring up", e))?;
+    Ok(tap)
+}
+
+// The enclave opens the connection with an HTTP request naming the
+// transport endpoint; frames follow the blank line without a response
+fn skip_request(enclave: &mut UnixStream) -> io::Result<()> {
+    let mut head = Vec::new();
+    let mut byte = [0u8; 1];
+    while !head.ends_with(b"\r\n\r\n") {
+        if head.len() > 4096 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "request head too long"));
+        }
+        enclave.read_exact(&mut byte)?;
+        head.push(byte[0]);
+    }
+    Ok(())
+}
+
+// Frames from the enclave to the TAP device, until the enclave disconnects
+fn to_tap(mut enclave: UnixStream, mut tap: File) -> io::Result<()> {
+    let mut frame = vec![0u8; FRAME_LEN];
+    loop {
+        let mut length = [0u8; 2];
+        enclave.read_exact(&mut length)?;
+        let length = u16::from_le_bytes(length) as usize;
+        enclave.read_exact(&mut frame[..length])?;
+        tap.write_all(&frame[..length])?;
+    }
+}
+
+// Frames from the TAP device to the enclave, until a write fails or the
+// connection is over
+fn from_tap(mut enclave: UnixStream, mut tap: File, stop: &AtomicBool) -> io::Result<()> {
+    let mut buf = vec![0u8; 2 + FRAME_LEN];
+    while !stop.load(Ordering::Relaxed) {
+        let mut ready = libc::pollfd { fd: tap.as_raw_fd(), events: libc::POLLIN, revents: 0 };
+        match unsafe { libc::poll(&mut ready, 1, STOP_POLL_MS) } {
+            0 => continue,
+            result if result < 0 => {
+                let e = io::Error::last_os_error();
+                if e.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(e);
+            }
+            _ => {}
+        }
+        let length = tap.read(&mut buf[2..])?;
+        buf[..2].copy_from_slice(&(length as u16).to_le_bytes());
+        enclave.write_all(&buf[..2 + length])?;
+    }
+    Ok(())
+}
+
+fn connect_tap(mut enclave: UnixStream, tap: &File) -> io::Result<()> {
+    skip_request(&mut enclave)?;
+    let (reader, writer) = (tap.try_clone()?, tap.try_clone()?);
+    let outgoing = enclave.try_clone()?;
+    let stop = AtomicBool::new(false);
+    // The reader is stopped and joined before the next connection starts
+    // its own, so frames are never read off the TAP device by a stale one
+    thread::scope(|scope| {
+        scope.spawn(|| from_tap(outgoing, reader, &stop));
+        let result = to_tap(enclave.try_clone()?, writer);
+        stop.store(true, Ordering::Relaxed);
+        let _ = enclave.shutdown(std::net::Shutdown::Both);
+        result
+    })
+}
+
+// Relay DNS queries sent to the gateway address to the upstream resolver
+fn relay_dns(address: &str, upstream: String) -> Result<(), SystemError> {
+    let socket = UdpSocket::bind((address, 53)).map_err(|e| SystemError {
+        message: format!("Failed to listen for DNS on {}:53: {}", address, e),
+    })?;
+    info!(upstream = upstream; "Relaying DNS queries");
+    let mut query = [0u8; 512];
+    loop {
+        let (length, client) = match socket.recv_from(&mut query) {
+            Ok(received) => received,
+            Err(e) => {
+                warn!("DNS receive failed: {}", e);
+                continue;
+            }
+        };
+        let (query, socket, upstream) = (query[..length].to_vec(), socket.try_clone(), upstream.clone());
+        thread::spawn(move || {
+            let answer = UdpSocket::bind("0.0.0.0:0").and_then(|relay| {
+                relay.set_read_timeout(Some(DNS_TIMEOUT))?;
+                relay.send_to(&query, &upstream)?;
+                let mut answer = vec![0u8; 4096];
+                let length = relay.recv(&mut answer)?;
+                answer.truncate(length);
+                Ok(answer)
+            });
+            match (answer, socket) {
+                (Ok(answer), Ok(socket)) => {
+                    let _ = socket.send_to(&answer, client);
+                }
+                (Err(e), _) | (_, Err(e)) => warn!(upstream = upstream; "DNS relay failed: {}", e),
+            }
+        });
+    }
+}
+
+// Give the enclave's tap0 a peer on the host. Only one enclave connection
+// is served at a time; a new one is accepted once the previous one ends.
+pub fn serve(cid: u32, config: &Egress) -> Result<(), SystemError> {
+    let tap = open_tap(config)?;
+    if let Some(upstream) = config.dns_upstream.clone() {
+        let address = config.address.clone();
+        thread::spawn(move || {
+            if let Err(e) = relay_dns(&address, upstream) {
+                warn!("{}", e);
+            }
+        });
+    }
+    let listener = Listener::bind(config.port)?;
+    info!(port = config.port, device = config.device; "Egress endpoint listening");
+    loop {
+        match listener.accept() {
+            Ok((enclave, peer)) if peer == cid => {
+                info!(device = config.device; "Enclave network connected");
+                if let Err(e) = connect_tap(enclave, &tap) {
+                    warn!(device = config.device; "Enclave network disconnected: {}", e);
+                }
+            }
+            Ok((_, peer)) => warn!(port = config.port, peer = peer; "Refused vsock connection"),
+            Err(e) => listener.accept_failed(&e),
+        }
+    }
+}
@@ -0,0 +1,111 @@
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::os::unix::net::UnixStream;
+use std::thread;
+use system::{info, warn, SystemError};
+use crate::config::{HttpProxy, Inbound, Outbound};
+use crate::vsock::{self, bridge, Listener};
+
+// Longest CONNECT request head accepted
+const MAX_REQUEST_HEAD: usize = 8192;
+
+// Forward connections to a host TCP port into the enclave. Replaces
+// `socat TCP-LISTEN:<port>,fork VSOCK-CONNECT:<cid>:<port>`.
+pub fn inbound(cid: u32, config: &Inbound) -> Result<(), SystemError> {
+    let listener = TcpListener::bind(&config.listen).map_err(|e| SystemError {
+        message: format!("Failed to listen on {}: {}", config.listen, e),
+    })?;
+    info!(listen = config.listen, port = config.port; "Forwarding into the enclave");
+    let port = config.port;
+    for connection in listener.incoming() {
+        let connection = match connection {
+            Ok(connection) => connection,
+            Err(e) => {
+                warn!(listen = config.listen; "Accept failed: {}", e);
+                continue;
+            }
+        };
+        thread::spawn(move || match vsock::connect(cid, port) {
+            Ok(enclave) => {
+                let _ = bridge(connection, enclave);
+            }
+            Err(e) => warn!("{}", e),
+        });
+    }
+    Ok(())
+}
+
+// Forward connections from the enclave to one fixed remote, the way
+// vsock-proxy does for KMS
+pub fn outbound(cid: u32, config: &Outbound) -> Result<(), SystemError> {
+    let listener = Listener::bind(config.port)?;
+    info!(port = config.port, remote = config.remote; "Forwarding out of the enclave");
+    let remote = config.remote.clone();
+    listener.serve(cid, move |enclave| match TcpStream::connect(&remote) {
+        Ok(connection) => {
+            let _ = bridge(enclave, connection);
+        }
+        Err(e) => warn!(remote = remote; "Connect failed: {}", e),
+    });
+    Ok(())
+}
+
+// Request head up to and including the blank line. Read a byte at a time
+// so nothing sent after it is consumed.
+fn read_head(stream: &mut UnixStream) -> Result<String, String> {
+    let mut head = Vec::new();
+    let mut byte = [0u8; 1];
+    while !head.ends_with(b"\r\n\r\n") {
+        if head.len() >= MAX_REQUEST_HEAD {
+            return Err("request head too long".to_string());
+        }
+        match stream.read(&mut byte) {
+            Ok(0) => return Err("connection closed".to_string()),
+            Ok(_) => head.push(byte[0]),
+            Err(e) => return Err(e.to_string()),
+        }
+    }
+    String::from_utf8(head).map_err(|_| "request head is not UTF-8".to_string())
+}
+
+fn respond(stream: &mut UnixStream, status: &str) {
+    let _ = stream.write_all(format!("HTTP/1.1 {}\r\ncontent-length: 0\r\n\r\n", status).as_bytes());
+}
+
+fn connect_request(mut enclave: UnixStream, allow: &[String]) {
+    let head = match read_head(&mut enclave) {
+        Ok(head) => head,
+        Err(e) => {
+            warn!("HTTP proxy request rejected: {}", e);
+            return respond(&mut enclave, "400 Bad Request");
+        }
+    };
+    let mut request = head.lines().next().unwrap_or_default().split_whitespace();
+    let (method, target) = (request.next(), request.next().unwrap_or_default());
+    if method != Some("CONNECT") {
+        return respond(&mut enclave, "405 Method Not Allowed");
+    }
+    if !allow.iter().any(|allowed| allowed.eq_ignore_ascii_case(target)) {
+        warn!(target = target; "HTTP proxy destination not allowed");
+        return respond(&mut enclave, "403 Forbidden");
+    }
+    let remote = match TcpStream::connect(target) {
+        Ok(remote) => remote,
+        Err(e) => {
+            warn!(target = target; "HTTP proxy connect failed: {}", e);
+            return respond(&mut enclave, "502 Bad Gateway");
+        }
+    };
+    if enclave.write_all(b"HTTP/1.1 200 Connection established\r\n\r\n").is_ok() {
+        let _ = bridge(enclave, remote);
+    }
+}
+
+// HTTP CONNECT proxy for the enclave, limited to allowed destinations
+pub fn http_proxy(cid: u32, config: &HttpProxy) -> Result<(), SystemError> {
+    let listener = Listener::bind(config.port)?;
+    info!(port = config.port, allowed = config.allow.join(","); "HTTP proxy listening");
+    let allow = config.allow.clone();
+    listener.serve(cid, move |enclave| connect_request(enclave, &allow));
+    Ok(())
+}
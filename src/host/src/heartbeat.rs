@@ -0,0 +1,26 @@
+use std::io::{Read, Write};
+use system::{info, warn, SystemError};
+use crate::config::Heartbeat;
+use crate::vsock::Listener;
+
+// Byte the enclave sends once it has booted, echoed back as acknowledgement
+const HEARTBEAT: u8 = 0xb7;
+
+// Acknowledge heartbeats from the enclave, as nitro-cli does while it
+// starts one
+pub fn serve(cid: u32, config: &Heartbeat) -> Result<(), SystemError> {
+    let listener = Listener::bind(config.port)?;
+    info!(port = config.port; "Heartbeat listener ready");
+    listener.serve(cid, |mut enclave| {
+        let mut buf = [0u8; 1];
+        match enclave.read_exact(&mut buf) {
+            Ok(()) if buf[0] == HEARTBEAT => match enclave.write_all(&buf) {
+                Ok(()) => info!("Enclave heartbeat acknowledged"),
+                Err(e) => warn!("Heartbeat acknowledgement failed: {}", e),
+            },
+            Ok(()) => warn!("Unexpected heartbeat {:#04x}", buf[0]),
+            Err(e) => warn!("Heartbeat connection failed: {}", e),
+        }
+    });
+    Ok(())
+}
@@ -0,0 +1,90 @@
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use system::{info, warn, SystemError};
+use crate::config::Logs;
+use crate::vsock::Listener;
+
+// Delay before the console is attached again after nitro-cli exits
+const CONSOLE_RETRY: Duration = Duration::from_secs(5);
+
+// Log file shared by every source. Each record is one line prefixed with
+// the time it was received and where it came from.
+#[derive(Clone)]
+struct Collector {
+    file: Arc<Mutex<File>>,
+}
+
+impl Collector {
+    fn open(path: &str) -> Result<Self, SystemError> {
+        let file = OpenOptions::new().create(true).append(true).open(path).map_err(|e| SystemError {
+            message: format!("Failed to open log file {}: {}", path, e),
+        })?;
+        Ok(Collector { file: Arc::new(Mutex::new(file)) })
+    }
+
+    fn record(&self, source: &str, line: &str) {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        let record = format!("{}.{:03} {}: {}\n", now.as_secs(), now.subsec_millis(), source, line.trim_end());
+        if let Err(e) = self.file.lock().expect("!lock").write_all(record.as_bytes()) {
+            warn!("Failed to write log record: {}", e);
+        }
+    }
+
+    fn collect(&self, source: &str, reader: impl std::io::Read) {
+        for line in BufReader::new(reader).lines() {
+            match line {
+                Ok(line) => self.record(source, &line),
+                Err(e) => {
+                    warn!(source = source; "Log stream failed: {}", e);
+                    return;
+                }
+            }
+        }
+    }
+}
+
+// Follow the console of a debug-mode enclave, reattaching whenever
+// nitro-cli exits, e.g. because the enclave restarted
+fn follow_console(collector: Collector, enclave: String) {
+    loop {
+        let child = Command::new("nitro-cli")
+            .args(["console", "--enclave-name", &enclave])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn();
+        match child {
+            Ok(mut child) => {
+                if let Some(stdout) = child.stdout.take() {
+                    collector.collect("console", stdout);
+                }
+                let _ = child.wait();
+            }
+            Err(e) => warn!("Failed to run nitro-cli console: {}", e),
+        }
+        thread::sleep(CONSOLE_RETRY);
+    }
+}
+
+// Write the enclave's console, and with a port records streamed over
+// vsock, to one file
+pub fn serve(cid: u32, config: &Logs) -> Result<(), SystemError> {
+    let collector = Collector::open(&config.path)?;
+    info!(path = config.path; "Collecting enclave logs");
+    let console = config.console.clone().map(|enclave| {
+        let collector = collector.clone();
+        thread::spawn(move || follow_console(collector, enclave))
+    });
+    if let Some(port) = config.port {
+        let listener = Listener::bind(port)?;
+        listener.serve(cid, move |enclave| collector.collect("vsock", enclave));
+    }
+    if let Some(console) = console {
+        let _ = console.join();
+    }
+    Ok(())
+}
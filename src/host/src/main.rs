@@ -0,0 +1,68 @@
+use std::sync::mpsc;
+use std::thread;
+use config::{Config, CONFIG_PATH};
+use system::{error, info, SystemError};
+
+mod config;
+mod egress;
+mod forward;
+mod heartbeat;
+mod logs;
+mod vsock;
+
+// Host companion of the enclave: everything the parent instance provides,
+// run from one configuration file. Usage: enclave-host [config]
+fn main() {
+    let path = std::env::args().nth(1).unwrap_or_else(|| CONFIG_PATH.to_string());
+    let config = match Config::load(&path) {
+        Ok(config) => config,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    system::log::set_level(config.log_level);
+    let config: &'static Config = Box::leak(Box::new(config));
+    let cid = config.cid;
+
+    // Components run until they fail; the daemon exits with the first
+    // failure so the service manager restarts it as a whole
+    let (failed, failure) = mpsc::channel::<SystemError>();
+    let spawn = |component: Box<dyn FnOnce() -> Result<(), SystemError> + Send>| {
+        let failed = failed.clone();
+        thread::spawn(move || {
+            if let Err(e) = component() {
+                let _ = failed.send(e);
+            }
+        });
+    };
+    for inbound in &config.inbound {
+        spawn(Box::new(move || forward::inbound(cid, inbound)));
+    }
+    for outbound in &config.outbound {
+        spawn(Box::new(move || forward::outbound(cid, outbound)));
+    }
+    if let Some(http_proxy) = &config.http_proxy {
+        spawn(Box::new(move || forward::http_proxy(cid, http_proxy)));
+    }
+    if let Some(egress) = &config.egress {
+        spawn(Box::new(move || egress::serve(cid, egress)));
+    }
+    if let Some(heartbeat) = &config.heartbeat {
+        spawn(Box::new(move || heartbeat::serve(cid, heartbeat)));
+    }
+    if let Some(logs) = &config.logs {
+        spawn(Box::new(move || logs::serve(cid, logs)));
+    }
+    drop(failed);
+    info!(cid = cid, config = path; "Enclave host daemon started");
+
+    match failure.recv() {
+        Ok(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+        // Every component finished without failing
+        Err(_) => info!("Nothing left to serve"),
+    }
+}
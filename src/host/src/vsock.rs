@@ -0,0 +1,130 @@
+use std::io::{self, Read, Write};
+use std::mem::{size_of, zeroed};
+use std::net::{Shutdown, TcpStream};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::net::UnixStream;
+use std::thread;
+use std::time::Duration;
+use system::{socket_connect, socket_listen, warn, SystemError};
+
+// How long accepting pauses when the daemon is out of resources
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+// std has no vsock type, but a connected vsock socket reads, writes and
+// shuts down like any other stream socket, so connections are handled as
+// UnixStream. Its address methods do not apply and are never used.
+fn stream(fd: i32) -> UnixStream {
+    unsafe { UnixStream::from_raw_fd(fd) }
+}
+
+// Keep sockets out of the processes the daemon runs, e.g. nitro-cli
+fn close_on_exec(fd: i32) -> i32 {
+    unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) };
+    fd
+}
+
+pub fn connect(cid: u32, port: u32) -> Result<UnixStream, SystemError> {
+    socket_connect(libc::AF_VSOCK, port, cid).map(close_on_exec).map(stream)
+}
+
+pub struct Listener {
+    fd: OwnedFd,
+    port: u32,
+}
+
+impl Listener {
+    pub fn bind(port: u32) -> Result<Self, SystemError> {
+        let fd = close_on_exec(socket_listen(libc::AF_VSOCK, port, libc::VMADDR_CID_ANY)?);
+        Ok(Listener { fd: unsafe { OwnedFd::from_raw_fd(fd) }, port })
+    }
+
+    // Next connection and the CID it came from
+    pub fn accept(&self) -> io::Result<(UnixStream, u32)> {
+        use libc::{accept4, sockaddr, sockaddr_vm, socklen_t, SOCK_CLOEXEC};
+        let mut address: sockaddr_vm = unsafe { zeroed() };
+        let mut length = size_of::<sockaddr_vm>() as socklen_t;
+        let fd = unsafe {
+            accept4(
+                self.fd.as_raw_fd(),
+                &mut address as *mut _ as *mut sockaddr,
+                &mut length,
+                SOCK_CLOEXEC,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok((stream(fd), address.svm_cid))
+    }
+
+    // Log a failed accept, pausing after running out of descriptors or
+    // memory, which accepting again right away would not fix
+    pub fn accept_failed(&self, e: &io::Error) {
+        match e.raw_os_error().unwrap_or(0) {
+            libc::EMFILE | libc::ENFILE | libc::ENOBUFS | libc::ENOMEM => {
+                warn!(port = self.port; "vsock accept failed, backing off: {}", e);
+                thread::sleep(ACCEPT_BACKOFF);
+            }
+            _ => warn!(port = self.port; "vsock accept failed: {}", e),
+        }
+    }
+
+    // Accept connections from `cid` forever, handling each on its own
+    // thread. Connections from other CIDs are dropped.
+    pub fn serve<F>(&self, cid: u32, handler: F)
+    where
+        F: Fn(UnixStream) + Clone + Send + 'static,
+    {
+        loop {
+            match self.accept() {
+                Ok((connection, peer)) if peer == cid => {
+                    let handler = handler.clone();
+                    thread::spawn(move || handler(connection));
+                }
+                Ok((_, peer)) => warn!(port = self.port, peer = peer; "Refused vsock connection"),
+                Err(e) => self.accept_failed(&e),
+            }
+        }
+    }
+}
+
+// Stream with independently closable directions
+pub trait Duplex: Read + Write + Send + Sized + 'static {
+    fn try_clone(&self) -> io::Result<Self>;
+    fn shutdown_write(&self);
+}
+
+impl Duplex for UnixStream {
+    fn try_clone(&self) -> io::Result<Self> {
+        UnixStream::try_clone(self)
+    }
+
+    fn shutdown_write(&self) {
+        let _ = self.shutdown(Shutdown::Write);
+    }
+}
+
+impl Duplex for TcpStream {
+    fn try_clone(&self) -> io::Result<Self> {
+        TcpStream::try_clone(self)
+    }
+
+    fn shutdown_write(&self) {
+        let _ = self.shutdown(Shutdown::Write);
+    }
+}
+
+// Copy both directions until each side has closed, like socat. Blocks the
+// calling thread.
+pub fn bridge<A: Duplex, B: Duplex>(a: A, b: B) -> io::Result<()> {
+    let (mut a_reader, mut b_reader) = (a.try_clone()?, b.try_clone()?);
+    let (mut a_writer, mut b_writer) = (a, b);
+    let upstream = thread::spawn(move || {
+        let _ = io::copy(&mut a_reader, &mut b_writer);
+        b_writer.shutdown_write();
+    });
+    let _ = io::copy(&mut b_reader, &mut a_writer);
+    a_writer.shutdown_write();
+    let _ = upstream.join();
+    Ok(())
+}
@@ -0,0 +1,187 @@
+use serde::{Deserialize, Serialize};
+use server::Health;
+use std::os::unix::fs::PermissionsExt;
+use system::manifest;
+use system::{error, info, warn, SystemError};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+
+// Prefix of user data in attestation documents minted for services. The
+// full user data is the prefix, the caller's uid as a big-endian u32 and
+// the 32 byte digest the caller supplied.
+pub const USER_DATA_PREFIX: &[u8] = b"enclave-broker/v1\0";
+
+const DIGEST_LEN: usize = 32;
+const MAX_NONCE_LEN: usize = 64;
+const MAX_PUBLIC_KEY_LEN: usize = 1024;
+const MAX_EXTEND_LEN: usize = 1024;
+// Longest request line accepted from a client
+const MAX_REQUEST_LEN: usize = 8192;
+
+// One request per line, as JSON with binary fields hex encoded
+#[derive(Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case", deny_unknown_fields)]
+enum Request {
+    Random {
+        size: usize,
+    },
+    Attestation {
+        digest: String,
+        nonce: Option<String>,
+        public_key: Option<String>,
+    },
+    ExtendPcr {
+        index: u16,
+        data: String,
+    },
+}
+
+#[derive(Serialize)]
+struct Response {
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+fn encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn decode(field: &str, text: &str, max: usize) -> Result<Vec<u8>, String> {
+    if !text.is_ascii() || text.len() % 2 != 0 || text.len() / 2 > max {
+        return Err(format!("{} must be at most {} hex encoded bytes", field, max));
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&text[i..i + 2], 16))
+        .collect::<Result<_, _>>()
+        .map_err(|_| format!("{} is not hex", field))
+}
+
+fn user_data(uid: u32, digest: &[u8]) -> Vec<u8> {
+    let mut data = USER_DATA_PREFIX.to_vec();
+    data.extend_from_slice(&uid.to_be_bytes());
+    data.extend_from_slice(digest);
+    data
+}
+
+async fn blocking<F>(f: F) -> Result<Vec<u8>, String>
+where
+    F: FnOnce() -> Result<Vec<u8>, SystemError> + Send + 'static,
+{
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.message)
+}
+
+async fn handle(request: Request, uid: u32, config: &manifest::Broker) -> Result<Vec<u8>, String> {
+    match request {
+        Request::Random { size } => {
+            if size == 0 || size > config.max_random_bytes {
+                return Err(format!("size must be between 1 and {}", config.max_random_bytes));
+            }
+            blocking(move || aws::get_entropy(size)).await
+        }
+        Request::Attestation { digest, nonce, public_key } => {
+            let digest = decode("digest", &digest, DIGEST_LEN)?;
+            if digest.len() != DIGEST_LEN {
+                return Err(format!("digest must be {} bytes", DIGEST_LEN));
+            }
+            let nonce = nonce.map(|n| decode("nonce", &n, MAX_NONCE_LEN)).transpose()?;
+            let public_key = public_key
+                .map(|k| decode("public_key", &k, MAX_PUBLIC_KEY_LEN))
+                .transpose()?;
+            let user_data = user_data(uid, &digest);
+            blocking(move || aws::attestation_document(Some(user_data), nonce, public_key)).await
+        }
+        Request::ExtendPcr { index, data } => {
+            if !config.allowed_pcrs.contains(&index) {
+                return Err(format!("PCR {} may not be extended", index));
+            }
+            let data = decode("data", &data, MAX_EXTEND_LEN)?;
+            blocking(move || aws::extend_pcr(index, data)).await
+        }
+    }
+}
+
+async fn client(stream: UnixStream, config: &manifest::Broker) -> std::io::Result<()> {
+    let uid = stream.peer_cred()?.uid();
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+    loop {
+        let mut line = String::new();
+        let limit = MAX_REQUEST_LEN as u64 + 1;
+        if (&mut reader).take(limit).read_line(&mut line).await? == 0 {
+            return Ok(());
+        }
+        let oversized = line.len() > MAX_REQUEST_LEN;
+        let result = if oversized {
+            Err("request too long".to_string())
+        } else {
+            match serde_json::from_str(&line) {
+                Ok(request) => handle(request, uid, config).await,
+                Err(e) => Err(format!("invalid request: {}", e)),
+            }
+        };
+        let response = match result {
+            Ok(data) => Response { ok: true, data: Some(encode(&data)), error: None },
+            Err(e) => Response { ok: false, data: None, error: Some(e) },
+        };
+        let mut text = serde_json::to_string(&response).expect("response serializes");
+        text.push('\n');
+        writer.write_all(text.as_bytes()).await?;
+        // The rest of an oversized line cannot be resynchronized
+        if oversized {
+            return Ok(());
+        }
+    }
+}
+
+fn listen(path: &str) -> Result<UnixListener, SystemError> {
+    let error = |e: std::io::Error| SystemError {
+        message: format!("Failed to listen on {}: {}", path, e),
+    };
+    if let Some(parent) = std::path::Path::new(path).parent() {
+        std::fs::create_dir_all(parent).map_err(error)?;
+    }
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(error(e)),
+        _ => {}
+    }
+    let listener = UnixListener::bind(path).map_err(error)?;
+    // Any service may connect, the broker decides what it may do
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o666)).map_err(error)?;
+    Ok(listener)
+}
+
+// Serve NSM requests from services on a Unix socket
+pub async fn serve(config: manifest::Broker, health: Health) {
+    health.register("broker");
+    let listener = match listen(&config.socket) {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("{}", e);
+            health.down("broker", e.message);
+            return;
+        }
+    };
+    info!(socket = config.socket; "NSM broker listening");
+    health.up("broker", config.socket.clone());
+    let config = std::sync::Arc::new(config);
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                let config = config.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = client(stream, &config).await {
+                        warn!("NSM broker client failed: {}", e);
+                    }
+                });
+            }
+            Err(e) => warn!("NSM broker failed to accept: {}", e),
+        }
+    }
+}
@@ -0,0 +1,65 @@
+use std::backtrace::Backtrace;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use std::thread;
+use crate::shutdown::{self, Action};
+use system::{boot_time, BootReport};
+
+// Halt instead of rebooting so the console can be inspected
+static DEBUG: AtomicBool = AtomicBool::new(false);
+
+pub fn set_debug(debug: bool) {
+    DEBUG.store(debug, Ordering::Relaxed);
+}
+
+// Write a crash report line by line to the console and the kernel log
+fn emit(lines: &[String]) {
+    let mut kmsg = OpenOptions::new().write(true).open("/dev/kmsg").ok();
+    let mut stderr = std::io::stderr().lock();
+    for line in lines {
+        let _ = writeln!(stderr, "{} {}", boot_time(), line);
+        if let Some(kmsg) = kmsg.as_mut() {
+            // <2> is KERN_CRIT. Each write is a single kmsg record.
+            let _ = kmsg.write_all(format!("<2>init: {}\n", line).as_bytes());
+        }
+    }
+    let _ = stderr.flush();
+    let _ = std::io::stdout().flush();
+    unsafe { libc::sync() };
+}
+
+// Report every panic with a backtrace and the boot state. A panic on the
+// main thread would take down PID 1, so reboot (or halt in debug mode)
+// cleanly instead of letting the kernel panic.
+pub fn install(report: Arc<RwLock<BootReport>>) {
+    std::panic::set_hook(Box::new(move |info| {
+        let current = thread::current();
+        let name = current.name().unwrap_or("<unnamed>");
+        let mut lines = vec![format!("PANIC in thread '{}': {}", name, info)];
+        lines.extend(
+            Backtrace::force_capture()
+                .to_string()
+                .lines()
+                .map(|l| l.to_string()),
+        );
+        // The panic may have happened while the report was locked
+        match report.try_read() {
+            Ok(report) => lines.push(format!("Boot report: {}", report.to_json())),
+            Err(_) => lines.push("Boot report: unavailable".to_string()),
+        }
+        emit(&lines);
+
+        if name != "main" {
+            return;
+        }
+        if DEBUG.load(Ordering::Relaxed) {
+            emit(&["Halting after panic (enclave.debug)".to_string()]);
+            shutdown::power(Action::Halt);
+        } else {
+            emit(&["Rebooting after panic".to_string()]);
+            shutdown::power(Action::Reboot);
+        }
+    }));
+}
@@ -0,0 +1,227 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, OnceLock};
+use system::manifest::ServiceConfig;
+use system::{info, warn, SystemError};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+// Development mode: init runs as an ordinary process against a directory
+// laid out like the initramfs, without Nitro hardware. The NSM is mocked,
+// the parent instance is reached over vsock loopback and Redis is served by
+// an in-process stand-in.
+//
+//   init --emulate [--root DIR] [--cmdline ARGS] [--redis ADDRESS]
+//
+// Stages that need PID 1 or root (rootfs, console, cgroups, kernel
+// modules) are skipped. Services run without their sandbox and limits, and
+// services whose binary is missing under the root are not started.
+// Instead of halting, rebooting or powering off, init exits. Heartbeats
+// and metrics are answered by an enclave-host configured with cid = 1.
+pub struct Emulation {
+    // Stands in for / when resolving image paths
+    pub root: PathBuf,
+    // Kernel command line the boot options are parsed from
+    pub cmdline: String,
+    // Where the Redis stand-in listens
+    pub redis: String,
+}
+
+static EMULATION: OnceLock<Emulation> = OnceLock::new();
+
+// Parent instance as seen from the enclave
+const PARENT_CID: u32 = 3;
+const DEFAULT_REDIS: &str = "127.0.0.1:6379";
+
+// Parse the arguments init was started with. Emulation is enabled by
+// --emulate; the other options only take effect with it.
+pub fn configure(mut args: impl Iterator<Item = String>) -> Result<(), SystemError> {
+    let mut emulate = false;
+    let mut emulation = Emulation {
+        root: PathBuf::from("."),
+        cmdline: String::new(),
+        redis: DEFAULT_REDIS.to_string(),
+    };
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next().ok_or_else(|| SystemError { message: format!("{} needs a value", arg) })
+        };
+        match arg.as_str() {
+            "--emulate" => emulate = true,
+            "--root" => emulation.root = PathBuf::from(value()?),
+            "--cmdline" => emulation.cmdline = value()?,
+            "--redis" => emulation.redis = value()?,
+            _ => return Err(SystemError { message: format!("Unknown argument {}", arg) }),
+        }
+    }
+    if emulate {
+        let _ = EMULATION.set(emulation);
+    }
+    Ok(())
+}
+
+pub fn get() -> Option<&'static Emulation> {
+    EMULATION.get()
+}
+
+pub fn enabled() -> bool {
+    EMULATION.get().is_some()
+}
+
+// Path inside the image, relocated under the emulation root
+pub fn resolve(path: &str) -> PathBuf {
+    match EMULATION.get() {
+        Some(emulation) => emulation.root.join(path.trim_start_matches('/')),
+        None => PathBuf::from(path),
+    }
+}
+
+// CID heartbeats and metrics are sent to
+pub fn parent_cid() -> u32 {
+    if enabled() {
+        libc::VMADDR_CID_LOCAL
+    } else {
+        PARENT_CID
+    }
+}
+
+// Keep what a service needs to be ordered and probed, drop what needs
+// privileges an emulated init does not have
+pub fn service_config(config: ServiceConfig) -> ServiceConfig {
+    if !enabled() {
+        return config;
+    }
+    ServiceConfig {
+        after: config.after,
+        ready: config.ready,
+        ready_timeout_secs: config.ready_timeout_secs,
+        ..ServiceConfig::default()
+    }
+}
+
+type Store = Arc<Mutex<HashMap<Vec<u8>, Vec<u8>>>>;
+
+fn bulk(value: Option<&Vec<u8>>) -> Vec<u8> {
+    match value {
+        Some(value) => {
+            let mut reply = format!("${}\r\n", value.len()).into_bytes();
+            reply.extend_from_slice(value);
+            reply.extend_from_slice(b"\r\n");
+            reply
+        }
+        None => b"$-1\r\n".to_vec(),
+    }
+}
+
+fn execute(store: &Store, command: &[Vec<u8>]) -> Vec<u8> {
+    let name = command.first().map(|name| name.to_ascii_uppercase()).unwrap_or_default();
+    let mut store = store.lock().expect("!lock");
+    match (name.as_slice(), &command[1..]) {
+        (b"PING", []) => b"+PONG\r\n".to_vec(),
+        (b"PING", [message]) | (b"ECHO", [message]) => bulk(Some(message)),
+        // Options such as EX are accepted and ignored
+        (b"SET", [key, value, ..]) => {
+            store.insert(key.clone(), value.clone());
+            b"+OK\r\n".to_vec()
+        }
+        (b"GET", [key]) => bulk(store.get(key)),
+        (b"DEL", keys) if !keys.is_empty() => {
+            let removed = keys.iter().filter(|key| store.remove(*key).is_some()).count();
+            format!(":{}\r\n", removed).into_bytes()
+        }
+        (b"EXISTS", keys) if !keys.is_empty() => {
+            let found = keys.iter().filter(|key| store.contains_key(*key)).count();
+            format!(":{}\r\n", found).into_bytes()
+        }
+        (b"BGSAVE", []) => b"+Background saving started\r\n".to_vec(),
+        (b"SAVE", []) | (b"SELECT", [_]) | (b"CLIENT", _) => b"+OK\r\n".to_vec(),
+        _ => format!("-ERR unknown command '{}'\r\n", String::from_utf8_lossy(&name)).into_bytes(),
+    }
+}
+
+// One command in RESP array form, or inline as typed into a terminal.
+// None once the client has disconnected.
+async fn read_command(reader: &mut BufReader<TcpStream>) -> std::io::Result<Option<Vec<Vec<u8>>>> {
+    let invalid = |what: &str| std::io::Error::new(std::io::ErrorKind::InvalidData, what.to_string());
+    let mut line = String::new();
+    if reader.read_line(&mut line).await? == 0 {
+        return Ok(None);
+    }
+    let Some(count) = line.strip_prefix('*') else {
+        return Ok(Some(line.split_whitespace().map(|word| word.as_bytes().to_vec()).collect()));
+    };
+    let count: usize = count.trim().parse().map_err(|_| invalid("bad array length"))?;
+    let mut command = Vec::with_capacity(count.min(16));
+    for _ in 0..count {
+        line.clear();
+        reader.read_line(&mut line).await?;
+        let length: usize = line
+            .strip_prefix('$')
+            .and_then(|length| length.trim().parse().ok())
+            .ok_or_else(|| invalid("expected a bulk string"))?;
+        let mut argument = vec![0u8; length + 2];
+        reader.read_exact(&mut argument).await?;
+        argument.truncate(length);
+        command.push(argument);
+    }
+    Ok(Some(command))
+}
+
+async fn redis_client(stream: TcpStream, store: Store) -> std::io::Result<()> {
+    let mut reader = BufReader::new(stream);
+    while let Some(command) = read_command(&mut reader).await? {
+        if command.is_empty() {
+            continue;
+        }
+        let reply = execute(&store, &command);
+        reader.get_mut().write_all(&reply).await?;
+    }
+    Ok(())
+}
+
+async fn serve_redis(listener: std::net::TcpListener) -> std::io::Result<()> {
+    let listener = TcpListener::from_std(listener)?;
+    let store = Store::default();
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                let store = store.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = redis_client(stream, store).await {
+                        warn!("Redis stand-in client failed: {}", e);
+                    }
+                });
+            }
+            Err(e) => warn!("Redis stand-in failed to accept: {}", e),
+        }
+    }
+}
+
+// In-memory Redis stand-in with the commands the server uses. If the
+// address is taken, e.g. by a real local Redis, that server is used.
+// It runs on its own thread and runtime: the server calls Redis with
+// blocking clients, which would otherwise wait on a stand-in scheduled
+// behind them.
+pub fn spawn_redis(address: String) {
+    let listener = std::net::TcpListener::bind(&address).and_then(|listener| {
+        listener.set_nonblocking(true)?;
+        Ok(listener)
+    });
+    let listener = match listener {
+        Ok(listener) => listener,
+        Err(e) => {
+            warn!(address = address; "Redis stand-in not started, using what listens there: {}", e);
+            return;
+        }
+    };
+    info!(address = address; "Redis stand-in listening");
+    std::thread::spawn(move || {
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_io().build();
+        let result = runtime.and_then(|runtime| {
+            runtime.block_on(serve_redis(listener))
+        });
+        if let Err(e) = result {
+            warn!("Redis stand-in failed: {}", e);
+        }
+    });
+}
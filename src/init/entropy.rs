@@ -0,0 +1,94 @@
+use aws::NsmEntropySource;
+use server::Metrics;
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::Duration;
+use system::{add_entropy, info, warn, SystemError};
+
+// Handle to the background reseeder, used to request an immediate reseed
+#[derive(Clone)]
+pub struct Reseeder {
+    trigger: Sender<()>,
+}
+
+impl Reseeder {
+    pub fn reseed(&self) {
+        let _ = self.trigger.send(());
+    }
+}
+
+struct Daemon {
+    // Opened lazily and dropped after a failure so the next reseed reopens it
+    source: Option<NsmEntropySource>,
+    bytes: usize,
+    credit: bool,
+    metrics: Metrics,
+}
+
+impl Daemon {
+    fn reseed(&mut self) -> Result<(), SystemError> {
+        let source = match self.source.as_mut() {
+            Some(source) => source,
+            None => self.source.insert(NsmEntropySource::open()?),
+        };
+        let sample = source.sample(self.bytes).inspect_err(|_| self.source = None)?;
+        add_entropy(&sample, self.credit)
+    }
+
+    fn run(mut self, requests: Receiver<()>, interval: Option<Duration>) {
+        loop {
+            let trigger = match interval {
+                Some(interval) => match requests.recv_timeout(interval) {
+                    Ok(()) => "demand",
+                    Err(RecvTimeoutError::Timeout) => "interval",
+                    Err(RecvTimeoutError::Disconnected) => {
+                        thread::sleep(interval);
+                        "interval"
+                    }
+                },
+                None => match requests.recv() {
+                    Ok(()) => "demand",
+                    Err(_) => return,
+                },
+            };
+            let result = self.reseed();
+            let outcome = if result.is_ok() { "ok" } else { "error" };
+            self.metrics
+                .entropy_reseeds
+                .with_label_values(&[trigger, outcome])
+                .inc();
+            match result {
+                Ok(()) => self.metrics.entropy_seeded_bytes.inc_by(self.bytes as u64),
+                Err(e) => {
+                    warn!(trigger = trigger; "Failed to reseed entropy: {}", e);
+                    self.metrics.entropy_seed_failures.inc();
+                }
+            }
+        }
+    }
+}
+
+// Mix fresh NSM entropy into the kernel pool on an interval and on demand
+pub fn spawn_reseeder(
+    bytes: usize,
+    interval: Option<Duration>,
+    credit: bool,
+    metrics: Metrics,
+) -> Reseeder {
+    let (trigger, requests) = channel();
+    let daemon = Daemon { source: None, bytes, credit, metrics };
+    thread::Builder::new()
+        .name("reseeder".into())
+        .spawn(move || daemon.run(requests, interval))
+        .expect("!thread");
+    match interval {
+        Some(interval) => info!(
+            "Reseeding {} bytes of entropy every {}s (credit: {})",
+            bytes,
+            interval.as_secs(),
+            credit
+        ),
+        None => info!("Periodic entropy reseeding disabled"),
+    }
+    Reseeder { trigger }
+}
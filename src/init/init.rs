@@ -1,336 +1,360 @@
-use libc::{fcntl, F_GETFL, F_SETFL, O_NONBLOCK};
-use polling::{Event, Events, Poller};
-use server::start_server;
-use std::fs::OpenOptions;
-use std::io::{self, BufRead, BufReader, Write};
-use std::num::NonZero;
-use std::os::fd::AsRawFd;
-use std::path::PathBuf;
+use server::{start_admin, start_server, AppState, Health, Shutdown};
 use std::thread;
-use std::{
-    fs,
-    io::Read,
-    os::unix::fs::PermissionsExt,
-    process::{Command, Stdio},
-    sync::{Arc, Mutex},
+use std::time::{Duration, Instant};
+use std::fs;
+use supervisor::{supervise, Service};
+use system::{
+    credit_entropy, debug, error, freopen, info, interface_up, seed_entropy, uptime,
+    wait_for_crng, warn, BootOptions, BootReport, SystemError,
 };
-use system::{dmesg, freopen, mount, seed_entropy};
+use system::boot::Stage;
+use system::cgroup::{self, CGROUP_ROOT, CONTROLLERS};
+use system::log::Level;
+use system::mounts::{self, Mount};
+use system::manifest::{self, FailureAction, Manifest, MANIFEST_PATH};
 //TODO: Feature flag
+use aws::heartbeat::Heartbeat;
 use aws::{get_entropy, init_platform};
-use tokio::task;
+use shutdown::Action;
+
+mod broker;
+mod crash;
+mod emulate;
+mod entropy;
+mod output;
+mod selftest;
+mod shutdown;
+mod startup;
+mod supervisor;
+
+// Interface created by the /vm network proxy
+const NETWORK_INTERFACE: &str = "tap0";
+
+// Interval of metrics pushes enabled with enclave.metrics_port
+const METRICS_PUSH_INTERVAL: Duration = Duration::from_secs(15);
 
 // Mount common filesystems with conservative permissions
-fn init_rootfs() {
+fn init_rootfs() -> Result<(), SystemError> {
     use libc::{MS_NODEV, MS_NOEXEC, MS_NOSUID};
     let no_dse = MS_NODEV | MS_NOSUID | MS_NOEXEC;
     let no_se = MS_NOSUID | MS_NOEXEC;
-    let args = [
-        ("devtmpfs", "/dev", "devtmpfs", no_se, "mode=0755"),
-        ("devtmpfs", "/dev", "devtmpfs", no_se, "mode=0755"),
-        ("devpts", "/dev/pts", "devpts", no_se, ""),
-        ("shm", "/dev/shm", "tmpfs", no_dse, "mode=0755"),
-        ("proc", "/proc", "proc", no_dse, "hidepid=2"),
-        ("tmpfs", "/run", "tmpfs", no_dse, "mode=0755"),
-        ("tmpfs", "/tmp", "tmpfs", no_dse, ""),
-        ("sysfs", "/sys", "sysfs", no_dse, ""),
-        (
-            "cgroup_root",
-            "/sys/fs/cgroup",
-            "tmpfs",
-            no_dse,
-            "mode=0755",
-        ),
+    // /proc goes first so the rest of the table can see what the kernel
+    // already mounted, e.g. /dev with CONFIG_DEVTMPFS_MOUNT
+    let table = [
+        Mount::new("proc", "/proc", "proc").flags(no_dse).option("hidepid=2"),
+        Mount::new("devtmpfs", "/dev", "devtmpfs").flags(no_se).option("mode=0755"),
+        Mount::new("devpts", "/dev/pts", "devpts").flags(no_se),
+        Mount::new("shm", "/dev/shm", "tmpfs").flags(no_dse).option("mode=0755").size("10%"),
+        Mount::tmpfs("/run").flags(no_dse).option("mode=0755").size("10%"),
+        Mount::tmpfs("/tmp").flags(no_dse).size("25%"),
+        Mount::new("sysfs", "/sys", "sysfs").flags(no_dse),
+        Mount::new("cgroup2", CGROUP_ROOT, "cgroup2").flags(no_dse).option("nsdelegate"),
     ];
-    for (src, target, fstype, flags, data) in args {
-        match mount(src, target, fstype, flags, data) {
-            Ok(()) => dmesg(format!("Mounted {}", target)),
-            Err(e) => eprintln!("{}", e),
-        }
-    }
+    mounts::apply_all(&table)
 }
 
 // Initialize console with stdin/stdout/stderr
-fn init_console() {
+fn init_console() -> Result<(), SystemError> {
     let args = [
         ("/dev/console", "r", 0),
         ("/dev/console", "w", 1),
         ("/dev/console", "w", 2),
     ];
+    let mut result = Ok(());
     for (filename, mode, file) in args {
-        match freopen(filename, mode, file) {
-            Ok(()) => {}
-            Err(e) => eprintln!("{}", e),
+        if let Err(e) = freopen(filename, mode, file) {
+            error!("{}", e);
+            result = Err(e);
         }
     }
-}
-
-fn set_nonblocking<H>(handle: &H, nonblocking: bool) -> std::io::Result<()>
-where
-    H: Read + AsRawFd,
-{
-    let fd = handle.as_raw_fd();
-    let flags = unsafe { fcntl(fd, F_GETFL, 0) };
-    if flags < 0 {
-        return Err(std::io::Error::last_os_error());
-    }
-    let flags = if nonblocking {
-        flags | O_NONBLOCK
-    } else {
-        flags & !O_NONBLOCK
-    };
-    let res = unsafe { fcntl(fd, F_SETFL, flags) };
-    if res != 0 {
-        return Err(std::io::Error::last_os_error());
-    }
-    Ok(())
-}
-
-// Pipe streams are blocking, we need separate threads to monitor them without blocking the primary thread.
-fn child_stream_to_vec<R>(mut stream: R) -> Arc<Mutex<Vec<u8>>>
-where
-    R: Read + Send + 'static,
-{
-    let out = Arc::new(Mutex::new(Vec::new()));
-    let vec = out.clone();
-    thread::Builder::new()
-        .name("child_stream_to_vec".into())
-        .spawn(move || loop {
-            let mut buf = [0];
-            match stream.read(&mut buf) {
-                Err(err) => {
-                    println!("{}] Error reading from stream: {}", line!(), err);
-                    break;
-                }
-                Ok(got) => {
-                    if got == 0 {
-                        break;
-                    } else if got == 1 {
-                        vec.lock().expect("!lock").push(buf[0])
-                    } else {
-                        println!("{}] Unexpected number of bytes: {}", line!(), got);
-                        break;
-                    }
-                }
-            }
-        })
-        .expect("!thread");
-    out
+    result
 }
 
 fn debug_filesystem() {
-    dmesg("Debugging filesystem:".to_string());
+    debug!("Debugging filesystem:");
 
     // List root directory
     match fs::read_dir("/") {
         Ok(entries) => {
-            for entry in entries {
-                if let Ok(entry) = entry {
-                    dmesg(format!("Found in /: {:?}", entry.path()));
-                }
+            for entry in entries.flatten() {
+                debug!("Found in /: {:?}", entry.path());
             }
         }
-        Err(e) => dmesg(format!("Error reading /: {}", e)),
+        Err(e) => warn!("Error reading /: {}", e),
     }
 }
 
-fn start_redirection() -> Result<(), std::io::Error> {
-
-    let path = PathBuf::from("/vm").canonicalize()?;
-
-    let mut child = Command::new(path)
-        .stdin(Stdio::null())
-        .stdout(Stdio::piped()) // Pipe stdout
-        .stderr(Stdio::piped()) // Pipe stderr
-        .spawn()
-        .expect("Failed to start worker");
-
-    let stdout = child.stdout.take().unwrap();
-    set_nonblocking(&stdout, true)?;
-    let stderr = child.stderr.take().unwrap();
-    set_nonblocking(&stderr, true)?;
-
-    // Poll for both stdout and stderr
-    let handle = thread::spawn(move || {
-        let mut reader_out = BufReader::new(stdout);
-        let mut reader_err = BufReader::new(stderr);
-        let poller = Poller::new().unwrap();
-        let key_out = 1;
-        let key_err = 2;
-        let mut out_closed = false;
-        let mut err_closed = false;
-        let mut line = String::new();
-        let mut events = Events::with_capacity(NonZero::new(2).unwrap());
-
-        unsafe {
-            poller
-                .add(reader_out.get_ref(), Event::readable(key_out))
-                .unwrap();
-            poller
-                .add(reader_err.get_ref(), Event::readable(key_err))
-                .unwrap();
+// Kernel options, once /proc is mounted. An emulated init takes its
+// options from --cmdline and logs to stderr only.
+fn load_options() -> BootOptions {
+    let options = match emulate::get() {
+        Some(emulation) => Ok(BootOptions::parse(&emulation.cmdline)),
+        None => BootOptions::load(),
+    };
+    let options = match options {
+        Ok(options) => options,
+        Err(e) => {
+            error!("{}", e);
+            BootOptions::default()
         }
+    };
+    // enclave.debug implies debug logging unless a level is given
+    let default_level = if options.debug { Level::Debug } else { Level::Info };
+    let level = options.loglevel.unwrap_or(default_level);
+    if emulate::enabled() {
+        system::log::set_level(level);
+    } else {
+        system::log::init(level);
+    }
+    for warning in &options.warnings {
+        warn!("Ignoring kernel argument {}", warning);
+    }
+    options
+}
 
-        loop {
-            events.clear();
-            poller.wait(&mut events, None).unwrap();
-
-            for ev in events.iter() {
-                if ev.key == key_out {
-                    let len = match reader_out.read_line(&mut line) {
-                        Ok(len) => len,
-                        Err(e) => {
-                            println!("stdout error: {}", e);
-                            0
-                        }
-                    };
-                    if len == 0 {
-                        out_closed = true;
-                        poller.delete(reader_out.get_ref()).unwrap();
-                    } else {
-                        print!("[STDOUT] {}", line);
-                        line.clear();
-                        poller
-                            .modify(reader_out.get_ref(), Event::readable(key_out))
-                            .unwrap();
-                    }
-                }
-                if ev.key == key_err {
-                    let len = match reader_err.read_line(&mut line) {
-                        Ok(len) => len,
-                        Err(e) => {
-                            println!("stderr error: {}", e);
-                            0
-                        }
-                    };
-                    if len == 0 {
-                        err_closed = true;
-                        poller.delete(reader_err.get_ref()).unwrap();
-                    } else {
-                        print!("[STDERR] {}", line);
-                        line.clear();
-                        poller
-                            .modify(reader_err.get_ref(), Event::readable(key_err))
-                            .unwrap();
-                    }
-                }
-            }
+// Boot manifest named by the kernel options. The image must ship one; only
+// a development root may leave it out and boot with the defaults.
+fn load_manifest(options: &BootOptions) -> Result<Manifest, SystemError> {
+    let path = emulate::resolve(options.manifest.as_deref().unwrap_or(MANIFEST_PATH));
+    if !emulate::enabled() && !path.exists() {
+        return Err(SystemError { message: format!("Manifest {} not found", path.display()) });
+    }
+    Manifest::load(&path.to_string_lossy())
+}
 
-            if out_closed && err_closed {
-                println!("Stream closed, exiting process thread");
-                break;
-            }
+// Run a boot stage and record it in the shared boot report
+fn stage<T>(
+    state: &AppState,
+    name: &str,
+    f: impl FnOnce() -> Result<T, SystemError>,
+) -> Result<T, SystemError> {
+    let start = uptime().as_secs_f64();
+    let started = Instant::now();
+    let result = f();
+    let duration_ms = started.elapsed().as_secs_f64() * 1000.0;
+    state
+        .boot_report
+        .write()
+        .expect("!lock")
+        .record(name, start, duration_ms, &result);
+    result
+}
+
+// Stop booting if a critical stage failed, as configured by the manifest
+fn enforce_policy(state: &AppState, policy: &manifest::Boot, stage: &str) {
+    let report = state.boot_report.read().expect("!lock");
+    let failed = report.get(stage).is_some_and(|s| s.error.is_some());
+    if !failed || !policy.is_critical(stage) {
+        return;
+    }
+    report.print();
+    match policy.on_critical_failure {
+        FailureAction::Halt => {
+            error!(stage = stage; "Critical boot stage failed, halting");
+            shutdown::power(Action::Halt);
         }
-    });
+        FailureAction::Reboot => {
+            error!(stage = stage; "Critical boot stage failed, rebooting");
+            shutdown::power(Action::Reboot);
+        }
+        FailureAction::Continue => {
+            warn!(stage = stage; "Critical boot stage failed, continuing");
+        }
+    }
+}
 
-    handle.join().unwrap();
-    Ok(())
+// Summarize failed stages under the "boot" health component
+fn report_boot_health(report: &BootReport, policy: &manifest::Boot, health: &Health) {
+    let failed: Vec<&Stage> = report.stages.iter().filter(|s| s.error.is_some()).collect();
+    let detail = failed
+        .iter()
+        .map(|s| format!("{}: {}", s.name, s.error.as_deref().unwrap_or_default()))
+        .collect::<Vec<_>>()
+        .join("; ");
+    if failed.is_empty() {
+        health.up("boot", "all stages succeeded");
+    } else if failed.iter().any(|s| policy.is_critical(&s.name)) {
+        health.down("boot", detail);
+    } else {
+        health.degrade("boot", detail);
+    }
 }
 
+fn boot(state: &AppState) -> (BootOptions, Manifest) {
+    let health = &state.health;
+    health.register("boot");
+    // An emulated init runs on a booted system with its own console
+    if !emulate::enabled() {
+        let _ = stage(state, "rootfs", init_rootfs);
+        let _ = stage(state, "console", init_console);
+    }
+    let options = load_options();
+    crash::set_debug(options.debug);
+    // A manifest that fails to load leaves the default policy, under which
+    // this stage is critical, and no supervised service is started
+    let manifest = stage(state, "manifest", || load_manifest(&options)).unwrap_or_else(|e| {
+        error!("{}", e);
+        Manifest::default()
+    });
+    let policy = &manifest.boot;
+    enforce_policy(state, policy, "manifest");
+    enforce_policy(state, policy, "rootfs");
+    enforce_policy(state, policy, "console");
+
+    // Services run in child cgroups with the limits from the manifest
+    if !emulate::enabled() {
+        if let Err(e) = stage(state, "cgroups", || cgroup::enable_controllers(&CONTROLLERS)) {
+            error!("{}", e);
+        }
+        enforce_policy(state, policy, "cgroups");
+    }
 
-fn reverse_proxy() -> Result<(), std::io::Error> {
-
-    let path = PathBuf::from("/caddy").canonicalize()?;
-
-    let mut child = Command::new(path)
-        .arg("run")
-        .arg("--config")
-        .arg("/Caddyfile")
-        .stdin(Stdio::null())
-        .stdout(Stdio::piped()) // Pipe stdout
-        .stderr(Stdio::piped()) // Pipe stderr
-        .spawn()
-        .expect("Failed to start worker");
-
-    let stdout = child.stdout.take().unwrap();
-    set_nonblocking(&stdout, true)?;
-    let stderr = child.stderr.take().unwrap();
-    set_nonblocking(&stderr, true)?;
-
-    // Poll for both stdout and stderr
-    let handle = thread::spawn(move || {
-        let mut reader_out = BufReader::new(stdout);
-        let mut reader_err = BufReader::new(stderr);
-        let poller = Poller::new().unwrap();
-        let key_out = 1;
-        let key_err = 2;
-        let mut out_closed = false;
-        let mut err_closed = false;
-        let mut line = String::new();
-        let mut events = Events::with_capacity(NonZero::new(2).unwrap());
-
-        unsafe {
-            poller
-                .add(reader_out.get_ref(), Event::readable(key_out))
-                .unwrap();
-            poller
-                .add(reader_err.get_ref(), Event::readable(key_err))
-                .unwrap();
+    // Tell the hypervisor the enclave is up before anything slower runs
+    health.register("heartbeat");
+    let heartbeat = Heartbeat { cid: emulate::parent_cid(), ..Heartbeat::default() };
+    match stage(state, "heartbeat", || heartbeat.send()) {
+        Ok(attempts) => health.up("heartbeat", format!("acknowledged after {} attempt(s)", attempts)),
+        Err(e) => {
+            error!("{}", e);
+            health.down("heartbeat", e.message);
         }
+    };
+    enforce_policy(state, policy, "heartbeat");
 
-        loop {
-            events.clear();
-            poller.wait(&mut events, None).unwrap();
-
-            for ev in events.iter() {
-                if ev.key == key_out {
-                    let len = match reader_out.read_line(&mut line) {
-                        Ok(len) => len,
-                        Err(e) => {
-                            println!("stdout error: {}", e);
-                            0
-                        }
-                    };
-                    if len == 0 {
-                        out_closed = true;
-                        poller.delete(reader_out.get_ref()).unwrap();
-                    } else {
-                        print!("[STDOUT] {}", line);
-                        line.clear();
-                        poller
-                            .modify(reader_out.get_ref(), Event::readable(key_out))
-                            .unwrap();
-                    }
-                }
-                if ev.key == key_err {
-                    let len = match reader_err.read_line(&mut line) {
-                        Ok(len) => len,
-                        Err(e) => {
-                            println!("stderr error: {}", e);
-                            0
-                        }
-                    };
-                    if len == 0 {
-                        err_closed = true;
-                        poller.delete(reader_err.get_ref()).unwrap();
-                    } else {
-                        print!("[STDERR] {}", line);
-                        line.clear();
-                        poller
-                            .modify(reader_err.get_ref(), Event::readable(key_err))
-                            .unwrap();
-                    }
-                }
-            }
+    // The emulated NSM needs no kernel modules
+    health.register("nsm");
+    let platform = || {
+        if !emulate::enabled() {
+            return init_platform(&manifest.modules);
+        }
+        aws::mock::enable();
+        Ok(())
+    };
+    match stage(state, "platform", platform) {
+        Ok(()) if emulate::enabled() => health.up("nsm", "emulated"),
+        Ok(()) => health.up("nsm", format!("{} module(s) loaded", manifest.modules.len())),
+        Err(e) => {
+            error!("{}", e);
+            health.down("nsm", e.message);
+        }
+    };
+    enforce_policy(state, policy, "platform");
+
+    health.register("entropy");
+    let entropy = &manifest.entropy;
+    // Crediting entropy needs CAP_SYS_ADMIN
+    let credit = credit_entropy(entropy.credit) && !emulate::enabled();
+    match stage(state, "entropy", || seed_entropy(entropy.boot_bytes, get_entropy, credit)) {
+        Ok(size) => {
+            info!(bytes = size; "Seeded kernel with entropy");
+            state.metrics.entropy_seeded_bytes.inc_by(size as u64);
+            health.up("entropy", format!("{} bytes", size));
+        }
+        Err(e) => {
+            error!("{}", e);
+            state.metrics.entropy_seed_failures.inc();
+            health.down("entropy", e.message);
+        }
+    };
+    enforce_policy(state, policy, "entropy");
+
+    // Nothing may generate keys before the kernel CRNG is ready
+    health.register("crng");
+    let timeout = Duration::from_secs(entropy.crng_timeout_secs);
+    let ready = match stage(state, "crng", || wait_for_crng(Some(timeout))) {
+        Ok(waited) => {
+            info!(ms = waited.as_millis(); "CRNG ready");
+            health.up("crng", "initialized");
+            true
+        }
+        Err(e) => {
+            error!("{}", e);
+            health.down("crng", e.message);
+            false
+        }
+    };
+    enforce_policy(state, policy, "crng");
+    if !ready {
+        // The policy chose to continue, but services still wait for the CRNG
+        warn!("Holding services until the CRNG is initialized");
+        if wait_for_crng(None).is_ok() {
+            health.up("crng", "initialized late");
+        }
+    }
 
-            if out_closed && err_closed {
-                println!("Stream closed, exiting process thread");
-                break;
-            }
+    let report = state.boot_report.read().expect("!lock");
+    for stage in &report.stages {
+        state
+            .metrics
+            .boot_stage_seconds
+            .with_label_values(&[&stage.name])
+            .set(stage.duration_ms / 1000.0);
+    }
+    report_boot_health(&report, policy, health);
+    report.print();
+    drop(report);
+    (options, manifest)
+}
+
+// Services supervised by init, configured from the manifest. Their
+// sandboxes and limits come from it, so none are started when it failed to
+// load, and a service without its own section is not started either,
+// rather than running them unconfined.
+fn services(manifest: &Manifest, report: &BootReport) -> Vec<Service> {
+    if report.get("manifest").map_or(true, |s| s.error.is_some()) {
+        error!("Not starting supervised services without a manifest");
+        return Vec::new();
+    }
+    let service_config = |name| emulate::service_config(manifest.service(name));
+    let mut services = vec![
+        Service { name: "vm", path: "/vm", args: &[], config: service_config("vm") },
+        Service {
+            name: "caddy",
+            path: "/caddy",
+            args: &["run", "--config", "/Caddyfile"],
+            config: service_config("caddy"),
+        },
+    ];
+    services.retain(|service| {
+        let configured = manifest.services.contains_key(service.name);
+        if !configured {
+            error!(service = service.name; "Not started: no [services.{}] in the manifest", service.name);
         }
+        configured
     });
-
-    handle.join().unwrap();
-    Ok(())
+    // A development root need not contain every service
+    if emulate::enabled() {
+        services.retain(|service| {
+            let present = emulate::resolve(service.path).exists();
+            if !present {
+                warn!(service = service.name; "Not started: {} is missing", service.path);
+            }
+            present
+        });
+    }
+    services
 }
 
-fn boot() {
-    init_rootfs();
-    init_console();
-    init_platform();
-    match seed_entropy(4096, get_entropy) {
-        Ok(size) => dmesg(format!("Seeded kernel with entropy: {}", size)),
-        Err(e) => eprintln!("{}", e),
-    };
+// Track whether the network proxy has brought up its interface
+fn watch_network(health: Health) {
+    health.register("network");
+    let mut last = None;
+    loop {
+        let up = interface_up(NETWORK_INTERFACE);
+        let changed = match (&last, &up) {
+            (Some(Ok(a)), Ok(b)) => a != b,
+            _ => true,
+        };
+        if changed {
+            match &up {
+                Ok(true) => health.up("network", NETWORK_INTERFACE),
+                Ok(false) => health.down("network", format!("{} is not up", NETWORK_INTERFACE)),
+                Err(e) => health.down("network", e.message.clone()),
+            }
+        }
+        last = Some(up);
+        thread::sleep(Duration::from_secs(1));
+    }
 }
 
 // fn configure_dns() -> io::Result<()> {
@@ -358,122 +382,178 @@ fn boot() {
 
 #[tokio::main]
 async fn main() {
-    boot();
-    debug_filesystem();
-    dmesg("EnclaveOS Booted".to_string());
+    if let Err(e) = emulate::configure(std::env::args().skip(1)) {
+        error!("{}", e);
+        std::process::exit(2);
+    }
+    let state = AppState::default();
+    crash::install(state.boot_report.clone());
+    if let Some(emulation) = emulate::get() {
+        // Up before boot so the server's Redis checks find it
+        emulate::spawn_redis(emulation.redis.clone());
+        server::set_redis_url(format!("redis://{}", emulation.redis));
+    }
+    let (options, manifest) = boot(&state);
+    // Shutdown is requested by signals or a failed self-test and runs once
+    // everything has started
+    let (requests, mut requested) = tokio::sync::mpsc::unbounded_channel();
+    shutdown::listen(requests.clone());
+    let server_shutdown = Shutdown::default();
+    if options.debug {
+        debug_filesystem();
+    }
+    let entropy = &manifest.entropy;
+    let reseeder = entropy::spawn_reseeder(
+        entropy.reseed_bytes,
+        (entropy.reseed_interval_secs > 0)
+            .then(|| Duration::from_secs(entropy.reseed_interval_secs)),
+        credit_entropy(entropy.credit) && !emulate::enabled(),
+        state.metrics.clone(),
+    );
+    // SIGUSR1 requests an immediate reseed
+    tokio::spawn(async move {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut usr1 = match signal(SignalKind::user_defined1()) {
+            Ok(usr1) => usr1,
+            Err(e) => {
+                warn!("Failed to listen for SIGUSR1: {}", e);
+                return;
+            }
+        };
+        while usr1.recv().await.is_some() {
+            reseeder.reseed();
+        }
+    });
+    info!("EnclaveOS Booted");
     
     // match configure_dns() {
-    //     Ok(_) => println!("DNS configuration updated successfully."),
-    //     Err(e) => eprintln!("Failed to update DNS configuration: {}", e),
+    //     Ok(_) => info!("DNS configuration updated successfully."),
+    //     Err(e) => error!("Failed to update DNS configuration: {}", e),
     // }
 
-    // Spawn a task to handle the redirection so that it doesn't block the server
-    let redirection_task = tokio::task::spawn_blocking(|| {
-        start_redirection().unwrap();
-    });
-    let reverse_proxy =  tokio::task::spawn_blocking(|| {
-        reverse_proxy().unwrap();
-    }); 
-    // Start the server asynchronously
-    let server_task = tokio::spawn(async {
-        start_server().await;
-    });
-
-    let test_server = tokio::spawn(async {
-        let url = "http://0.0.0.0:8000";
-        print!("testing server now!");
-        match reqwest::get(url).await {
-            Ok(response) => match response.text().await {
-                Ok(text) => {
-                    println!("result {}", text);
-                }
-                Err(e) => {
-                    eprintln!("Failed to read response text: {}", e);
-                }
-            },
-            Err(e) => {
-                eprintln!("Failed to make GET request: {}", e);
-            }
-        }
+    // Services reach the NSM device only through the broker, so it starts
+    // before them
+    if manifest.broker.enabled {
+        let health = state.health.clone();
+        let mut config = manifest.broker.clone();
+        config.socket = emulate::resolve(&config.socket).to_string_lossy().into_owned();
+        tokio::spawn(broker::serve(config, health));
+    }
 
-        match reqwest::get(format!("{}/access-internet", url)).await {
-            Ok(response) => match response.text().await {
-                Ok(text) => {
-                    println!("result {}", text);
-                }
-                Err(e) => {
-                    eprintln!("Failed to read response text: {}", e);
-                }
-            },
-            Err(e) => {
-                eprintln!("Failed to make GET request: {}", e);
-            }
-        }
-        match reqwest::get(format!("{}/redis", url)).await {
-            Ok(response) => match response.text().await {
-                Ok(text) => {
-                    println!("result {}", text);
-                }
-                Err(e) => {
-                    eprintln!("Failed to read response text: {}", e);
-                }
-            },
-            Err(e) => {
-                eprintln!("Failed to make GET request: {}", e);
-            }
-        }
+    // Operators inspect and control supervised services over vsock
+    tokio::spawn(start_admin(manifest.admin.clone(), state.clone()));
 
+    let health = state.health.clone();
+    let network_task = tokio::task::spawn_blocking(move || watch_network(health));
+    if let Some(port) = options.metrics_port {
+        let cid = emulate::parent_cid();
+        let metrics = state.metrics.clone();
+        thread::spawn(move || metrics.push_vsock_forever(cid, port, METRICS_PUSH_INTERVAL));
+    }
 
-        let url2 = "http://127.0.0.1:8000";
-        print!("testing server now w/ diff url!");
-        match reqwest::get(url2).await {
-            Ok(response) => match response.text().await {
-                Ok(text) => {
-                    println!("result {}", text);
-                }
-                Err(e) => {
-                    eprintln!("Failed to read response text: {}", e);
-                }
-            },
-            Err(e) => {
-                eprintln!("Failed to make GET request: {}", e);
+    // Supervised services run on their own threads so that they don't block
+    // the server. The server is ordered alongside them as "server".
+    let mut services = services(&manifest, &state.boot_report.read().expect("!lock"));
+    let mut names: Vec<&str> = services.iter().map(|s| s.name).collect();
+    names.push("server");
+    let order = startup::order(&names, &manifest).unwrap_or_else(|e| {
+        warn!("{}, starting services in declared order", e);
+        names.clone()
+    });
+    let mut server = None;
+    for name in order {
+        match services.iter().position(|s| s.name == name) {
+            Some(index) => {
+                let service = services.swap_remove(index);
+                let supervised = state.clone();
+                tokio::task::spawn_blocking(move || supervise(service, supervised));
+            }
+            None => {
+                server = Some(tokio::spawn(start_server(state.clone(), server_shutdown.clone())));
             }
         }
+        startup::wait_ready(name, &manifest, &state).await;
+    }
 
-        match reqwest::get(format!("{}/access-internet", url2)).await {
-            Ok(response) => match response.text().await {
-                Ok(text) => {
-                    println!("result {}", text);
-                }
-                Err(e) => {
-                    eprintln!("Failed to read response text: {}", e);
-                }
-            },
-            Err(e) => {
-                eprintln!("Failed to make GET request: {}", e);
-            }
+    let health = state.health.clone();
+    let selftest_state = state.clone();
+    tokio::spawn(async move {
+        let state = selftest_state;
+        if !selftest::enabled(&manifest.selftest, &options) {
+            return;
         }
-        match reqwest::get(format!("{}/redis", url2)).await {
-            Ok(response) => match response.text().await {
-                Ok(text) => {
-                    println!("result {}", text);
-                }
-                Err(e) => {
-                    eprintln!("Failed to read response text: {}", e);
-                }
-            },
-            Err(e) => {
-                eprintln!("Failed to make GET request: {}", e);
+        health.register("selftest");
+        let start = uptime().as_secs_f64();
+        let started = Instant::now();
+        let report = selftest::run(&manifest, &state).await;
+        report.print();
+        let result = report.result();
+        let duration_ms = started.elapsed().as_secs_f64() * 1000.0;
+        state
+            .boot_report
+            .write()
+            .expect("!lock")
+            .record("selftest", start, duration_ms, &result);
+        match result {
+            Ok(()) => health.up("selftest", "all checks passed"),
+            Err(_) if manifest.selftest.fail_boot => {
+                error!("Self-test failed, rebooting");
+                let _ = requests.send(Action::Reboot);
             }
+            Err(e) => health.down("selftest", e.message),
         }
     });
 
-    // Wait for both tasks to complete
-    tokio::join!(redirection_task, server_task, test_server, reverse_proxy);
+    // Init keeps running until asked to shut down. The sender is held by
+    // the signal listeners, so the channel never closes.
+    let action = requested.recv().await.unwrap_or(Action::PowerOff);
+    shutdown::run(action, &state, &server_shutdown, server).await;
+    drop(network_task);
 }
 
-// inside enclave socat connection
-// /usr/bin/socat -t 30 VSOCK-LISTEN:1000,fork,reuseaddr TCP:127.0.0.1:8000 &
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(name: &str, text: &str) -> (Result<Manifest, SystemError>, BootReport) {
+        let path = std::env::temp_dir().join(format!("init-{}-{}.toml", name, std::process::id()));
+        fs::write(&path, text).unwrap();
+        let manifest = Manifest::load(&path.to_string_lossy());
+        fs::remove_file(&path).unwrap();
+        let mut report = BootReport::default();
+        report.record("manifest", 0.0, 0.0, &manifest);
+        (manifest, report)
+    }
+
+    #[test]
+    fn malformed_manifest_starts_no_services() {
+        let (manifest, report) = load("malformed", "[services.vm]\nuser = \"not a uid\"\n");
+        assert!(manifest.is_err());
+        assert!(Manifest::default().boot.is_critical("manifest"));
+        assert!(services(&Manifest::default(), &report).is_empty());
+        // Nothing recorded means nothing was loaded either
+        assert!(services(&Manifest::default(), &BootReport::default()).is_empty());
+    }
+
+    #[test]
+    fn missing_manifest_fails_to_load() {
+        let options = BootOptions {
+            manifest: Some("/nonexistent/manifest.toml".to_string()),
+            ..BootOptions::default()
+        };
+        let e = load_manifest(&options).map(|_| ()).unwrap_err();
+        assert!(e.message.contains("not found"), "{}", e.message);
+    }
 
-// outside enclave socat connection
-// socat -t 30 TCP-LISTEN:80,fork,reuseaddr VSOCK-CONNECT:7777:1000 &
+    #[test]
+    fn loaded_manifest_configures_services() {
+        let (manifest, report) = load("valid", "[services.vm]\nuser = 1000\nno_new_privs = true\n");
+        let manifest = manifest.unwrap();
+        let services = services(&manifest, &report);
+        let vm = services.iter().find(|s| s.name == "vm").unwrap();
+        assert_eq!(vm.config.user, Some(1000));
+        assert!(vm.config.no_new_privs);
+        // caddy has no section, so it would run with the default config
+        assert!(services.iter().all(|s| s.name != "caddy"));
+    }
+}
@@ -0,0 +1,108 @@
+use server::{Line, Logs, Stream};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use system::{boot_time, uptime};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+// Longer lines are split, the remainder following as another line
+const MAX_LINE: usize = 4096;
+// Lines per second a service may log, with bursts up to LINE_BURST
+const LINE_RATE: f64 = 200.0;
+const LINE_BURST: f64 = 1000.0;
+
+// Token bucket shared by the streams of one service
+struct Limiter {
+    tokens: f64,
+    last: Instant,
+    suppressed: u64,
+}
+
+impl Limiter {
+    fn new() -> Self {
+        Limiter { tokens: LINE_BURST, last: Instant::now(), suppressed: 0 }
+    }
+
+    // Whether a line may be logged, and how many were dropped before it
+    fn admit(&mut self) -> (bool, u64) {
+        let now = Instant::now();
+        let refill = now.duration_since(self.last).as_secs_f64() * LINE_RATE;
+        self.tokens = (self.tokens + refill).min(LINE_BURST);
+        self.last = now;
+        if self.tokens < 1.0 {
+            self.suppressed += 1;
+            return (false, 0);
+        }
+        self.tokens -= 1.0;
+        (true, std::mem::take(&mut self.suppressed))
+    }
+}
+
+// Tags the output of one service and sends it to the console and the
+// service's ring buffer
+#[derive(Clone)]
+pub struct Multiplexer {
+    service: &'static str,
+    logs: Logs,
+    limiter: Arc<Mutex<Limiter>>,
+}
+
+impl Multiplexer {
+    pub fn new(service: &'static str, logs: Logs) -> Self {
+        Multiplexer { service, logs, limiter: Arc::new(Mutex::new(Limiter::new())) }
+    }
+
+    fn emit(&self, stream: Stream, bytes: &[u8]) {
+        let (admitted, suppressed) = self.limiter.lock().expect("!lock").admit();
+        if suppressed > 0 {
+            self.record(Stream::Init, format!("suppressed {} lines", suppressed));
+        }
+        if admitted {
+            let text = String::from_utf8_lossy(bytes);
+            self.record(stream, text.trim_end_matches('\r').to_string());
+        }
+    }
+
+    fn record(&self, stream: Stream, text: String) {
+        println!("{} {}/{}: {}", boot_time(), self.service, stream.as_str(), text);
+        let line = Line { time: uptime().as_secs_f64(), stream, text };
+        self.logs.push(self.service, line);
+    }
+
+    // Forward a stream line by line until it closes
+    pub async fn forward<R>(self, stream: Stream, mut reader: R)
+    where
+        R: AsyncRead + Unpin,
+    {
+        let mut pending = Vec::new();
+        let mut buf = [0u8; 4096];
+        loop {
+            let read = match reader.read(&mut buf).await {
+                Ok(0) => break,
+                Ok(read) => read,
+                Err(e) => {
+                    self.record(Stream::Init, format!("{} read failed: {}", stream.as_str(), e));
+                    break;
+                }
+            };
+            pending.extend_from_slice(&buf[..read]);
+            let mut start = 0;
+            while let Some(end) = pending[start..].iter().position(|b| *b == b'\n') {
+                self.emit(stream, &pending[start..start + end]);
+                start += end + 1;
+            }
+            pending.drain(..start);
+            while pending.len() >= MAX_LINE {
+                self.emit(stream, &pending[..MAX_LINE]);
+                pending.drain(..MAX_LINE);
+            }
+        }
+        // Output that did not end in a newline
+        if !pending.is_empty() {
+            self.emit(stream, &pending);
+        }
+        let suppressed = std::mem::take(&mut self.limiter.lock().expect("!lock").suppressed);
+        if suppressed > 0 {
+            self.record(Stream::Init, format!("suppressed {} lines", suppressed));
+        }
+    }
+}
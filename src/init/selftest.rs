@@ -0,0 +1,157 @@
+use server::{redis_ping, AppState};
+use std::time::{Duration, Instant};
+use system::manifest::{Check, Manifest, SelfTest};
+use system::{info, BootOptions, SystemError};
+use tokio::time::{sleep, timeout};
+
+// Delay between attempts of a check that has not passed yet
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+pub struct Outcome {
+    pub name: String,
+    pub passed: bool,
+    pub detail: String,
+    pub duration: Duration,
+}
+
+pub struct Report {
+    pub outcomes: Vec<Outcome>,
+}
+
+impl Report {
+    // Error naming every failed check
+    pub fn result(&self) -> Result<(), SystemError> {
+        let failed: Vec<&str> = self
+            .outcomes
+            .iter()
+            .filter(|o| !o.passed)
+            .map(|o| o.name.as_str())
+            .collect();
+        if failed.is_empty() {
+            Ok(())
+        } else {
+            Err(SystemError {
+                message: format!("Failed checks: {}", failed.join(", ")),
+            })
+        }
+    }
+
+    // One line per check followed by a summary line
+    pub fn print(&self) {
+        for o in &self.outcomes {
+            let verdict = if o.passed { "PASS" } else { "FAIL" };
+            info!(
+                "[{}] {} ({} ms) {}",
+                verdict,
+                o.name,
+                o.duration.as_millis(),
+                o.detail
+            );
+        }
+        let passed = self.outcomes.iter().filter(|o| o.passed).count();
+        info!(
+            "Self-test: {}/{} checks passed",
+            passed,
+            self.outcomes.len()
+        );
+    }
+}
+
+// Self-test is requested by the manifest, `enclave.selftest` on the kernel
+// command line takes precedence
+pub fn enabled(config: &SelfTest, options: &BootOptions) -> bool {
+    options.selftest.unwrap_or(config.enabled)
+}
+
+fn name(check: &Check) -> String {
+    match check {
+        Check::Http { url, .. } => format!("http {}", url),
+        Check::Dns { host } => format!("dns {}", host),
+        Check::Redis => "redis ping".to_string(),
+        Check::Attestation => "nsm attestation".to_string(),
+    }
+}
+
+async fn attempt(check: &Check, manifest: &Manifest, state: &AppState) -> Result<String, String> {
+    match check {
+        Check::Http { url, status } => {
+            let response = reqwest::get(url).await.map_err(|e| e.to_string())?;
+            if response.status().as_u16() == *status {
+                Ok(format!("status {}", status))
+            } else {
+                Err(format!("status {}, expected {}", response.status(), status))
+            }
+        }
+        Check::Dns { host } => {
+            let addrs: Vec<_> = tokio::net::lookup_host((host.as_str(), 0))
+                .await
+                .map_err(|e| e.to_string())?
+                .collect();
+            match addrs.first() {
+                Some(addr) => Ok(format!("{} addresses, first {}", addrs.len(), addr.ip())),
+                None => Err("no addresses".to_string()),
+            }
+        }
+        Check::Redis => redis_ping(&state.metrics)
+            .await
+            .map(|()| "PONG".to_string()),
+        Check::Attestation => {
+            let nonce = aws::get_entropy(32).map_err(|e| e.message)?;
+            let user_data = manifest
+                .attestation
+                .include_boot_report
+                .then(|| state.boot_report.read().expect("!lock").digest().to_vec());
+            let document = tokio::task::spawn_blocking(move || {
+                aws::attestation_document(user_data, Some(nonce), None)
+            })
+            .await
+            .map_err(|e| e.to_string())?
+            .map_err(|e| e.message)?;
+            Ok(format!("{} byte document", document.len()))
+        }
+    }
+}
+
+// Retry a check until it passes or its deadline expires, since services
+// may still be starting when the self-test begins
+async fn run_check(
+    check: &Check,
+    manifest: &Manifest,
+    state: &AppState,
+    limit: Duration,
+) -> Outcome {
+    let started = Instant::now();
+    let retries = async {
+        loop {
+            match attempt(check, manifest, state).await {
+                Ok(detail) => return Ok(detail),
+                Err(e) if started.elapsed() + RETRY_DELAY >= limit => return Err(e),
+                Err(_) => sleep(RETRY_DELAY).await,
+            }
+        }
+    };
+    let result = match timeout(limit, retries).await {
+        Ok(result) => result,
+        Err(_) => Err(format!("timed out after {}s", limit.as_secs())),
+    };
+    let (passed, detail) = match result {
+        Ok(detail) => (true, detail),
+        Err(e) => (false, e),
+    };
+    Outcome {
+        name: name(check),
+        passed,
+        detail,
+        duration: started.elapsed(),
+    }
+}
+
+pub async fn run(manifest: &Manifest, state: &AppState) -> Report {
+    let config = &manifest.selftest;
+    let limit = Duration::from_secs(config.timeout_secs);
+    let mut outcomes = Vec::with_capacity(config.checks.len());
+    for check in &config.checks {
+        outcomes.push(run_check(check, manifest, state, limit).await);
+    }
+    Report { outcomes }
+}
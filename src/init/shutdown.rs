@@ -0,0 +1,147 @@
+use server::{AppState, Shutdown, DRAIN_TIMEOUT};
+use std::time::{Duration, Instant};
+use crate::emulate;
+use system::{error, halt, info, poweroff, reboot, uptime, warn, SystemError};
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, timeout};
+
+// Time the server gets to flush stores after draining
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(5);
+// Time supervised services get to exit; the supervisor kills them after 10s
+const SERVICES_TIMEOUT: Duration = Duration::from_secs(12);
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// What init does once everything has stopped
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    PowerOff,
+    Reboot,
+    Halt,
+}
+
+// Carry out the action now. An emulated init exits instead, with 0 for
+// power off, 1 for halt and 2 for reboot.
+pub fn power(action: Action) {
+    if emulate::enabled() {
+        std::process::exit(match action {
+            Action::PowerOff => 0,
+            Action::Halt => 1,
+            Action::Reboot => 2,
+        });
+    }
+    match action {
+        Action::PowerOff => poweroff(),
+        Action::Reboot => reboot(),
+        Action::Halt => halt(),
+    }
+}
+
+// Turn the signals used to stop PID 1 into shutdown requests: SIGTERM
+// powers off, SIGINT reboots. SIGINT is what the kernel sends for
+// ctrl-alt-del once it no longer reboots by itself.
+pub fn listen(requests: UnboundedSender<Action>) {
+    use tokio::signal::unix::{signal, SignalKind};
+    if !emulate::enabled() {
+        unsafe { libc::reboot(libc::RB_DISABLE_CAD) };
+    }
+    for (kind, action) in [
+        (SignalKind::terminate(), Action::PowerOff),
+        (SignalKind::interrupt(), Action::Reboot),
+    ] {
+        let requests = requests.clone();
+        tokio::spawn(async move {
+            let mut signals = match signal(kind) {
+                Ok(signals) => signals,
+                Err(e) => {
+                    warn!("Failed to listen for shutdown signals: {}", e);
+                    return;
+                }
+            };
+            while signals.recv().await.is_some() {
+                let _ = requests.send(action);
+            }
+        });
+    }
+}
+
+// Record a shutdown step in the boot report, which crash reports include
+fn record(
+    state: &AppState,
+    stage: &str,
+    start: f64,
+    started: Instant,
+    result: &Result<(), SystemError>,
+) {
+    let duration_ms = started.elapsed().as_secs_f64() * 1000.0;
+    state.boot_report.write().expect("!lock").record(stage, start, duration_ms, result);
+}
+
+async fn stop_server(
+    shutdown: &Shutdown,
+    server: Option<JoinHandle<Result<(), SystemError>>>,
+) -> Result<(), SystemError> {
+    shutdown.trigger();
+    let Some(server) = server else {
+        return Ok(());
+    };
+    match timeout(DRAIN_TIMEOUT + FLUSH_TIMEOUT, server).await {
+        Ok(Ok(result)) => result,
+        Ok(Err(e)) => Err(SystemError { message: format!("Server task failed: {}", e) }),
+        Err(_) => Err(SystemError { message: "Server did not stop in time".to_string() }),
+    }
+}
+
+async fn stop_services(state: &AppState) -> Result<(), SystemError> {
+    let services = &state.services;
+    for service in services.list() {
+        services.stop(&service.name);
+    }
+    let started = Instant::now();
+    loop {
+        let running: Vec<String> = services
+            .list()
+            .into_iter()
+            .filter(|service| service.pid.is_some())
+            .map(|service| service.name)
+            .collect();
+        if running.is_empty() {
+            return Ok(());
+        }
+        if started.elapsed() >= SERVICES_TIMEOUT {
+            return Err(SystemError { message: format!("Still running: {}", running.join(", ")) });
+        }
+        sleep(POLL_INTERVAL).await;
+    }
+}
+
+// Stop the server, then the supervised services, then power off or reboot.
+// Each step is bounded so a stuck component cannot hold up the rest.
+pub async fn run(
+    action: Action,
+    state: &AppState,
+    shutdown: &Shutdown,
+    server: Option<JoinHandle<Result<(), SystemError>>>,
+) {
+    info!(action = format!("{:?}", action); "Shutting down");
+    state.health.down("shutdown", "in progress");
+
+    let (start, started) = (uptime().as_secs_f64(), Instant::now());
+    let result = stop_server(shutdown, server).await;
+    match &result {
+        Ok(()) => info!(ms = started.elapsed().as_millis(); "Server drained"),
+        Err(e) => error!("Server shutdown incomplete: {}", e),
+    }
+    record(state, "shutdown/server", start, started, &result);
+
+    let (start, started) = (uptime().as_secs_f64(), Instant::now());
+    let result = stop_services(state).await;
+    match &result {
+        Ok(()) => info!(ms = started.elapsed().as_millis(); "Services stopped"),
+        Err(e) => error!("Services shutdown incomplete: {}", e),
+    }
+    record(state, "shutdown/services", start, started, &result);
+
+    unsafe { libc::sync() };
+    power(action);
+}
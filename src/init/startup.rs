@@ -0,0 +1,133 @@
+use server::AppState;
+use std::collections::BTreeSet;
+use std::time::{Duration, Instant};
+use system::manifest::{Manifest, Probe};
+use system::{info, interface_up, uptime, warn, SystemError};
+use tokio::time::{sleep, timeout};
+
+// Readiness timeout of services that do not set ready_timeout_secs
+const DEFAULT_READY_TIMEOUT: Duration = Duration::from_secs(30);
+// Delay between rounds of probes
+const PROBE_INTERVAL: Duration = Duration::from_millis(250);
+// Limit of a single TCP or HTTP probe
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+// Order services so that each comes after everything it lists in `after`.
+// Services without dependencies keep their relative order.
+pub fn order<'a>(names: &[&'a str], manifest: &Manifest) -> Result<Vec<&'a str>, SystemError> {
+    fn visit<'a>(
+        name: &'a str,
+        names: &[&'a str],
+        manifest: &Manifest,
+        visiting: &mut BTreeSet<&'a str>,
+        ordered: &mut Vec<&'a str>,
+    ) -> Result<(), SystemError> {
+        if ordered.contains(&name) {
+            return Ok(());
+        }
+        if !visiting.insert(name) {
+            return Err(SystemError { message: format!("Service dependency cycle through {}", name) });
+        }
+        for dependency in manifest.service(name).after {
+            let dependency = names.iter().find(|n| **n == dependency).ok_or_else(|| {
+                SystemError { message: format!("Service {} depends on unknown {}", name, dependency) }
+            })?;
+            visit(dependency, names, manifest, visiting, ordered)?;
+        }
+        visiting.remove(name);
+        ordered.push(name);
+        Ok(())
+    }
+    let mut ordered = Vec::with_capacity(names.len());
+    for name in names {
+        visit(name, names, manifest, &mut BTreeSet::new(), &mut ordered)?;
+    }
+    Ok(ordered)
+}
+
+async fn probe(check: &Probe) -> Result<(), String> {
+    match check {
+        Probe::Tcp { address } => {
+            match timeout(PROBE_TIMEOUT, tokio::net::TcpStream::connect(address)).await {
+                Ok(Ok(_)) => Ok(()),
+                Ok(Err(e)) => Err(format!("{}: {}", address, e)),
+                Err(_) => Err(format!("{}: timed out", address)),
+            }
+        }
+        Probe::Interface { name } => match interface_up(name) {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(format!("{} is not up", name)),
+            Err(e) => Err(e.message),
+        },
+        Probe::File { path } => match std::path::Path::new(path).exists() {
+            true => Ok(()),
+            false => Err(format!("{} does not exist", path)),
+        },
+        Probe::Http { url, status } => {
+            let response = match timeout(PROBE_TIMEOUT, reqwest::get(url)).await {
+                Ok(response) => response.map_err(|e| e.to_string())?,
+                Err(_) => return Err(format!("{}: timed out", url)),
+            };
+            if response.status().as_u16() == *status {
+                Ok(())
+            } else {
+                Err(format!("{}: status {}, expected {}", url, response.status(), status))
+            }
+        }
+    }
+}
+
+async fn probe_until_ready(probes: &[Probe], limit: Duration) -> Result<(), SystemError> {
+    let started = Instant::now();
+    loop {
+        let mut failure = None;
+        for check in probes {
+            if let Err(e) = probe(check).await {
+                failure = Some(e);
+                break;
+            }
+        }
+        match failure {
+            None => return Ok(()),
+            Some(e) if started.elapsed() >= limit => {
+                return Err(SystemError {
+                    message: format!("not ready after {}s: {}", limit.as_secs(), e),
+                })
+            }
+            Some(_) => sleep(PROBE_INTERVAL).await,
+        }
+    }
+}
+
+// Wait for a started service to pass its readiness probes and record the
+// wait as a boot stage. A service that times out is reported, but its
+// dependents are started anyway.
+pub async fn wait_ready(name: &str, manifest: &Manifest, state: &AppState) {
+    let config = manifest.service(name);
+    let limit = config
+        .ready_timeout_secs
+        .map(Duration::from_secs)
+        .unwrap_or(DEFAULT_READY_TIMEOUT);
+    let start = uptime().as_secs_f64();
+    let started = Instant::now();
+    let result = probe_until_ready(&config.ready, limit).await;
+    let duration_ms = started.elapsed().as_secs_f64() * 1000.0;
+    let stage = format!("ready/{}", name);
+    match &result {
+        Ok(()) => info!(service = name, ms = duration_ms as u64; "Service ready"),
+        Err(e) => {
+            warn!(service = name; "Service {}", e.message);
+            state.health.degrade(&format!("service/{}", name), e.message.clone());
+        }
+    }
+    state
+        .metrics
+        .boot_stage_seconds
+        .with_label_values(&[&stage])
+        .set(duration_ms / 1000.0);
+    state
+        .boot_report
+        .write()
+        .expect("!lock")
+        .record(&stage, start, duration_ms, &result);
+}
@@ -0,0 +1,192 @@
+use crate::emulate;
+use crate::output::Multiplexer;
+use server::{AppState, Stream};
+use std::os::fd::AsRawFd;
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::thread;
+use std::time::Duration;
+use system::cgroup::Cgroup;
+use system::{error, info, warn};
+use system::manifest::ServiceConfig;
+use system::sandbox::Sandbox;
+
+// Delay before a service that exited is started again
+const RESTART_DELAY: Duration = Duration::from_secs(1);
+// Time a service has to exit after SIGTERM before it is killed
+const STOP_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Long running child process kept alive by init
+pub struct Service {
+    pub name: &'static str,
+    pub path: &'static str,
+    pub args: &'static [&'static str],
+    pub config: ServiceConfig,
+}
+
+impl Service {
+    // Health component the service reports under
+    fn component(&self) -> String {
+        format!("service/{}", self.name)
+    }
+
+    // Cgroup with the service's limits applied. Failures are logged and the
+    // service runs unconstrained rather than not at all. An emulated init
+    // does not manage cgroups.
+    fn cgroup(&self) -> Option<Cgroup> {
+        if emulate::enabled() {
+            return None;
+        }
+        let cgroup = Cgroup::create(self.name).and_then(|cgroup| {
+            cgroup.apply(&self.config)?;
+            Ok(cgroup)
+        });
+        match cgroup {
+            Ok(cgroup) => Some(cgroup),
+            Err(e) => {
+                warn!(service = self.name; "Running without limits: {}", e);
+                None
+            }
+        }
+    }
+
+    fn spawn(&self, cgroup: Option<&Cgroup>, sandbox: &Sandbox) -> std::io::Result<Child> {
+        let path = emulate::resolve(self.path).canonicalize()?;
+        let mut command = Command::new(path);
+        command
+            .args(self.args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped()) // Pipe stdout
+            .stderr(Stdio::piped()); // Pipe stderr
+        // Kept open until the child has been spawned
+        let procs = cgroup.and_then(|cgroup| cgroup.procs().ok());
+        if let Some(procs) = &procs {
+            // Join the cgroup between fork and exec; only async-signal-safe
+            // calls are allowed here
+            let fd = procs.as_raw_fd();
+            unsafe {
+                command.pre_exec(move || {
+                    if libc::write(fd, b"0".as_ptr() as _, 1) < 0 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                    Ok(())
+                });
+            }
+        }
+        // Namespaces, user and capabilities last, once the cgroup is joined
+        let sandbox = sandbox.clone();
+        unsafe {
+            command.pre_exec(move || sandbox.enter());
+        }
+        command.spawn()
+    }
+
+    fn run(
+        &self,
+        state: &AppState,
+        cgroup: Option<&Cgroup>,
+        sandbox: &Sandbox,
+        output: &Multiplexer,
+    ) -> std::io::Result<ExitStatus> {
+        use tokio::process::{ChildStderr, ChildStdout};
+        let mut child = self.spawn(cgroup, sandbox)?;
+        state.health.up(&self.component(), format!("pid {}", child.id()));
+        state.services.started(self.name, child.id());
+
+        let runtime = tokio::runtime::Handle::current();
+        let _context = runtime.enter();
+        let stdout = ChildStdout::from_std(child.stdout.take().expect("stdout is piped"))?;
+        let stderr = ChildStderr::from_std(child.stderr.take().expect("stderr is piped"))?;
+        let stdout = runtime.spawn(output.clone().forward(Stream::Stdout, stdout));
+        let stderr = runtime.spawn(output.clone().forward(Stream::Stderr, stderr));
+
+        let status = child.wait();
+        // Drain what the service wrote before it exited
+        runtime.block_on(async {
+            let _ = stdout.await;
+            let _ = stderr.await;
+        });
+        status
+    }
+}
+
+// Ask every process of a service to exit, killing whatever is left after
+// STOP_TIMEOUT. Without a cgroup only the direct child is signalled.
+fn stop(cgroup: Option<Cgroup>, pid: u32) {
+    use libc::{kill, SIGKILL, SIGTERM};
+    match &cgroup {
+        Some(cgroup) => {
+            if let Err(e) = cgroup.signal(SIGTERM) {
+                warn!(pid = pid; "Failed to stop service: {}", e);
+            }
+        }
+        None => unsafe {
+            kill(pid as i32, SIGTERM);
+        },
+    }
+    thread::spawn(move || {
+        thread::sleep(STOP_TIMEOUT);
+        // The instance is gone once its first process is; by then the
+        // cgroup may already hold its replacement
+        let remaining = match &cgroup {
+            Some(cgroup) => cgroup.pids().is_ok_and(|pids| pids.contains(&(pid as i32))),
+            None => unsafe { kill(pid as i32, 0) == 0 },
+        };
+        if remaining {
+            warn!(pid = pid; "Service did not exit after SIGTERM, killing it");
+            match &cgroup {
+                Some(cgroup) => {
+                    if let Err(e) = cgroup.kill() {
+                        warn!(pid = pid; "{}", e);
+                    }
+                }
+                None => unsafe {
+                    kill(pid as i32, SIGKILL);
+                },
+            }
+        }
+    });
+}
+
+// Run a service forever, restarting it whenever it exits unless it was
+// stopped on request.
+// Blocks the calling thread. Returns only if the service's sandbox
+// configuration is invalid.
+pub fn supervise(service: Service, state: AppState) {
+    let component = service.component();
+    state.health.register(&component);
+    // A service is never started with less isolation than configured
+    let sandbox = match Sandbox::new(&service.config) {
+        Ok(sandbox) => sandbox,
+        Err(e) => {
+            error!(service = service.name; "Not started: {}", e.message);
+            state.health.down(&component, e.message);
+            return;
+        }
+    };
+    let cgroup = service.cgroup();
+    let stop_cgroup = cgroup.clone();
+    state.services.register(service.name, move |pid| stop(stop_cgroup.clone(), pid));
+    let output = Multiplexer::new(service.name, state.logs.clone());
+    loop {
+        // Also holds back a restart when a stop was requested during the delay
+        state.services.wait_until_wanted(service.name);
+        let reason = match service.run(&state, cgroup.as_ref(), &sandbox, &output) {
+            Ok(status) => format!("exited: {}", status),
+            Err(e) => format!("failed to run: {}", e),
+        };
+        warn!(service = service.name; "Service {}", reason);
+        state.health.down(&component, reason);
+        if !state.services.exited(service.name) {
+            info!(service = service.name; "Service stopped on request");
+            state.health.down(&component, "stopped on request");
+            continue;
+        }
+        thread::sleep(RESTART_DELAY);
+        state
+            .metrics
+            .service_restarts
+            .with_label_values(&[service.name])
+            .inc();
+    }
+}
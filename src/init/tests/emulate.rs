@@ -0,0 +1,196 @@
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::os::fd::FromRawFd;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Boots init --emulate against a minimal root and drives it the way the
+// parent instance and clients would: heartbeat on vsock, then HTTP to the
+// server. Vsock needs the vsock_loopback module; without it the heartbeat
+// check is skipped and the boot report shows the heartbeat stage failed.
+
+const HEARTBEAT_PORT: u32 = 9000;
+const HEARTBEAT_VALUE: u8 = 0xB7;
+const SERVER: &str = "127.0.0.1:8000";
+const BOOT_TIMEOUT: Duration = Duration::from_secs(90);
+
+const MANIFEST: &str = r#"
+[broker]
+enabled = false
+
+[admin]
+enabled = false
+"#;
+
+fn root() -> PathBuf {
+    let root = std::env::temp_dir().join(format!("init-emulate-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&root);
+    std::fs::create_dir_all(&root).unwrap();
+    std::fs::write(root.join("manifest.toml"), MANIFEST).unwrap();
+    root
+}
+
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+}
+
+fn vsock(address: libc::sockaddr_vm, listen: bool) -> Option<std::fs::File> {
+    unsafe {
+        let fd = libc::socket(libc::AF_VSOCK, libc::SOCK_STREAM | libc::SOCK_CLOEXEC, 0);
+        if fd < 0 {
+            return None;
+        }
+        let file = std::fs::File::from_raw_fd(fd);
+        let pointer = &address as *const libc::sockaddr_vm as *const libc::sockaddr;
+        let length = std::mem::size_of::<libc::sockaddr_vm>() as libc::socklen_t;
+        let ok = if listen {
+            libc::bind(fd, pointer, length) == 0 && libc::listen(fd, 1) == 0
+        } else {
+            let timeout = libc::timeval { tv_sec: 2, tv_usec: 0 };
+            let option = &timeout as *const libc::timeval as *const libc::c_void;
+            let size = std::mem::size_of::<libc::timeval>() as libc::socklen_t;
+            libc::setsockopt(fd, libc::SOL_SOCKET, libc::SO_SNDTIMEO, option, size);
+            libc::connect(fd, pointer, length) == 0
+        };
+        ok.then_some(file)
+    }
+}
+
+fn vsock_address(cid: u32, port: u32) -> libc::sockaddr_vm {
+    let mut address: libc::sockaddr_vm = unsafe { std::mem::zeroed() };
+    address.svm_family = libc::AF_VSOCK as libc::sa_family_t;
+    address.svm_cid = cid;
+    address.svm_port = port;
+    address
+}
+
+fn accept(listener: &std::fs::File) -> Option<std::fs::File> {
+    use std::os::fd::AsRawFd;
+    let (address, length) = (std::ptr::null_mut(), std::ptr::null_mut());
+    let fd = unsafe { libc::accept4(listener.as_raw_fd(), address, length, libc::SOCK_CLOEXEC) };
+    (fd >= 0).then(|| unsafe { std::fs::File::from_raw_fd(fd) })
+}
+
+// Stand in for enclave-host on the heartbeat port, reporting the value the
+// first heartbeat carried. None when vsock loopback is unavailable.
+fn heartbeat_listener() -> Option<mpsc::Receiver<u8>> {
+    let listener = vsock(vsock_address(libc::VMADDR_CID_ANY, HEARTBEAT_PORT), true)?;
+    // Probe loopback before init relies on it
+    let _probe = vsock(vsock_address(libc::VMADDR_CID_LOCAL, HEARTBEAT_PORT), false)?;
+    accept(&listener)?;
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        while let Some(mut stream) = accept(&listener) {
+            let mut value = [0u8; 1];
+            if stream.read_exact(&mut value).is_ok() {
+                let _ = stream.write_all(&value);
+                let _ = sender.send(value[0]);
+            }
+        }
+    });
+    Some(receiver)
+}
+
+// Minimal HTTP/1.0 GET, returning the status code and body
+fn get(path: &str) -> std::io::Result<(u16, String)> {
+    let mut stream = TcpStream::connect(SERVER)?;
+    stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+    write!(stream, "GET {} HTTP/1.0\r\nHost: localhost\r\n\r\n", path)?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    let (head, body) = response.split_once("\r\n\r\n").unwrap_or((&response, ""));
+    let status = head.split(' ').nth(1).and_then(|code| code.parse().ok()).unwrap_or(0);
+    Ok((status, body.to_string()))
+}
+
+struct Init {
+    child: Child,
+    log: PathBuf,
+}
+
+impl Init {
+    fn boot(root: &Path, redis: &str) -> Init {
+        let log = root.join("init.log");
+        let output = std::fs::File::create(&log).unwrap();
+        let child = Command::new(env!("CARGO_BIN_EXE_init"))
+            .args(["--emulate", "--root"])
+            .arg(root)
+            .args(["--redis", redis])
+            .stdout(output.try_clone().unwrap())
+            .stderr(output)
+            .spawn()
+            .unwrap();
+        Init { child, log }
+    }
+
+    fn log(&self) -> String {
+        std::fs::read_to_string(&self.log).unwrap_or_default()
+    }
+
+    // Wait for the server to answer, failing with the boot log if init
+    // exits or takes too long
+    fn wait_for_server(&mut self) {
+        let started = Instant::now();
+        while get("/healthz").is_err() {
+            if let Some(status) = self.child.try_wait().unwrap() {
+                panic!("init exited with {} during boot:\n{}", status, self.log());
+            }
+            if started.elapsed() > BOOT_TIMEOUT {
+                panic!("server not up after {:?}:\n{}", BOOT_TIMEOUT, self.log());
+            }
+            thread::sleep(Duration::from_millis(200));
+        }
+    }
+}
+
+impl Drop for Init {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[test]
+fn boots_and_serves_requests() {
+    let root = root();
+    let heartbeats = heartbeat_listener();
+    let redis = format!("127.0.0.1:{}", free_port());
+    let mut init = Init::boot(&root, &redis);
+    init.wait_for_server();
+
+    let (_, body) = get("/healthz").unwrap();
+    let report: serde_json::Value = serde_json::from_str(&body).unwrap();
+    let components = &report["components"];
+    assert_eq!(components["nsm"]["detail"], "emulated", "{}", body);
+    assert_eq!(components["entropy"]["status"], "up", "{}", body);
+    assert_eq!(components["crng"]["status"], "up", "{}", body);
+
+    // Served through the Redis stand-in
+    let (status, body) = get("/redis").unwrap();
+    assert_eq!((status, body.as_str()), (200, "42"), "{}", init.log());
+
+    match heartbeats {
+        Some(heartbeats) => {
+            let value = heartbeats.recv_timeout(Duration::from_secs(10)).unwrap();
+            assert_eq!(value, HEARTBEAT_VALUE);
+            assert_eq!(components["heartbeat"]["status"], "up", "{}", report);
+        }
+        None => eprintln!("vsock loopback unavailable, heartbeat not checked"),
+    }
+
+    // SIGTERM powers off, which an emulated init turns into exit status 0
+    unsafe { libc::kill(init.child.id() as libc::pid_t, libc::SIGTERM) };
+    let started = Instant::now();
+    let status = loop {
+        if let Some(status) = init.child.try_wait().unwrap() {
+            break status;
+        }
+        assert!(started.elapsed() < Duration::from_secs(30), "init did not shut down:\n{}", init.log());
+        thread::sleep(Duration::from_millis(100));
+    };
+    assert!(status.success(), "init exited with {}:\n{}", status, init.log());
+    let _ = std::fs::remove_dir_all(&root);
+}
@@ -0,0 +1,352 @@
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::Path;
+use system::manifest::{DeviceKind, Image, ImageEntry};
+use system::SystemError;
+
+// Deterministic initramfs images in the newc ("070701") cpio format the
+// kernel unpacks. Every timestamp is zero, inodes are numbered in path
+// order and nothing is taken from the build host but file contents, so the
+// same manifest and sources always give the same bytes.
+
+const MAGIC: &str = "070701";
+const TRAILER: &str = "TRAILER!!!";
+// The archive is padded to a whole number of blocks, like gen_init_cpio
+const BLOCK_SIZE: usize = 512;
+// Permission and set-id bits a manifest may give
+const PERMISSIONS: u32 = 0o7777;
+
+// Member of the archive with its name, as stored, without the leading /
+struct Member<'a> {
+    name: &'a str,
+    mode: u32,
+    uid: u32,
+    gid: u32,
+    nlink: u32,
+    rdev: (u32, u32),
+    data: Vec<u8>,
+}
+
+// Writes members as newc records
+pub struct Writer<W: Write> {
+    out: W,
+    written: usize,
+    ino: u32,
+}
+
+impl<W: Write> Writer<W> {
+    pub fn new(out: W) -> Self {
+        Writer {
+            out,
+            written: 0,
+            ino: 0,
+        }
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> std::io::Result<()> {
+        self.out.write_all(bytes)?;
+        self.written += bytes.len();
+        Ok(())
+    }
+
+    fn pad(&mut self, alignment: usize) -> std::io::Result<()> {
+        let padding = (alignment - self.written % alignment) % alignment;
+        self.write(&vec![0u8; padding])
+    }
+
+    // Header, NUL terminated name and data, each padded to four bytes
+    fn member(&mut self, member: &Member, ino: u32) -> std::io::Result<()> {
+        let fields = [
+            ino,
+            member.mode,
+            member.uid,
+            member.gid,
+            member.nlink,
+            0, // mtime
+            member.data.len() as u32,
+            0, // device of the archive, major
+            0, // and minor
+            member.rdev.0,
+            member.rdev.1,
+            member.name.len() as u32 + 1,
+            0, // checksum, unused by newc
+        ];
+        let mut header = MAGIC.to_string();
+        for field in fields {
+            header.push_str(&format!("{:08X}", field));
+        }
+        self.write(header.as_bytes())?;
+        self.write(member.name.as_bytes())?;
+        self.write(&[0])?;
+        self.pad(4)?;
+        self.write(&member.data)?;
+        self.pad(4)
+    }
+
+    fn add(&mut self, member: &Member) -> std::io::Result<()> {
+        self.ino += 1;
+        self.member(member, self.ino)
+    }
+
+    pub fn file(
+        &mut self,
+        path: &str,
+        mode: u32,
+        owner: (u32, u32),
+        data: Vec<u8>,
+    ) -> std::io::Result<()> {
+        self.add(&member(path, libc::S_IFREG | mode, owner, data))
+    }
+
+    pub fn dir(&mut self, path: &str, mode: u32, owner: (u32, u32)) -> std::io::Result<()> {
+        let mut dir = member(path, libc::S_IFDIR | mode, owner, Vec::new());
+        dir.nlink = 2;
+        self.add(&dir)
+    }
+
+    pub fn device(
+        &mut self,
+        path: &str,
+        kind: DeviceKind,
+        rdev: (u32, u32),
+        mode: u32,
+        owner: (u32, u32),
+    ) -> std::io::Result<()> {
+        let kind = match kind {
+            DeviceKind::Char => libc::S_IFCHR,
+            DeviceKind::Block => libc::S_IFBLK,
+        };
+        let mut device = member(path, kind | mode, owner, Vec::new());
+        device.rdev = rdev;
+        self.add(&device)
+    }
+
+    pub fn symlink(&mut self, path: &str, target: &str, owner: (u32, u32)) -> std::io::Result<()> {
+        self.add(&member(
+            path,
+            libc::S_IFLNK | 0o777,
+            owner,
+            target.as_bytes().to_vec(),
+        ))
+    }
+
+    // Write the trailer and padding, returning the underlying writer
+    pub fn finish(mut self) -> std::io::Result<W> {
+        let trailer = Member {
+            nlink: 1,
+            ..member(TRAILER, 0, (0, 0), Vec::new())
+        };
+        self.member(&trailer, 0)?;
+        self.pad(BLOCK_SIZE)?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+fn member(path: &str, mode: u32, (uid, gid): (u32, u32), data: Vec<u8>) -> Member<'_> {
+    Member {
+        name: path.trim_start_matches('/'),
+        mode,
+        uid,
+        gid,
+        nlink: 1,
+        rdev: (0, 0),
+        data,
+    }
+}
+
+fn invalid(path: &str, reason: &str) -> SystemError {
+    SystemError {
+        message: format!("Invalid image entry {}: {}", path, reason),
+    }
+}
+
+fn permissions(path: &str, mode: Option<u32>, default: u32) -> Result<u32, SystemError> {
+    match mode.unwrap_or(default) {
+        mode if mode & !PERMISSIONS != 0 => Err(invalid(
+            path,
+            &format!("mode {:o} is not a permission mode", mode),
+        )),
+        mode => Ok(mode),
+    }
+}
+
+// Entries by path, checked to be absolute, unique and inside declared
+// directories. Path order puts every directory before its contents.
+fn entries(image: &Image) -> Result<BTreeMap<&str, &ImageEntry>, SystemError> {
+    let mut entries = BTreeMap::new();
+    for entry in &image.entries {
+        let path = entry.path();
+        let components: Vec<&str> = path.split('/').skip(1).collect();
+        if !path.starts_with('/') || components.iter().any(|c| matches!(*c, "" | "." | "..")) {
+            return Err(invalid(path, "paths must be absolute and normalized"));
+        }
+        if entries.insert(path, entry).is_some() {
+            return Err(invalid(path, "declared twice"));
+        }
+    }
+    for path in entries.keys() {
+        let parent = &path[..path.rfind('/').unwrap_or(0)];
+        if !parent.is_empty() && !matches!(entries.get(parent), Some(ImageEntry::Dir { .. })) {
+            return Err(invalid(
+                path,
+                &format!("{} is not declared as a directory", parent),
+            ));
+        }
+    }
+    Ok(entries)
+}
+
+// Build the image described by the manifest, reading file sources
+// relative to the sources directory
+pub fn build<W: Write>(image: &Image, sources: &Path, out: W) -> Result<W, SystemError> {
+    let io_error = |path: &str, e: std::io::Error| SystemError {
+        message: format!("Failed to write {} to the image: {}", path, e),
+    };
+    let mut writer = Writer::new(out);
+    for (path, entry) in entries(image)? {
+        let result = match entry {
+            ImageEntry::File {
+                source,
+                mode,
+                uid,
+                gid,
+                ..
+            } => {
+                let source = sources.join(source);
+                let data = std::fs::read(&source).map_err(|e| SystemError {
+                    message: format!("Failed to read {} for {}: {}", source.display(), path, e),
+                })?;
+                writer.file(path, permissions(path, *mode, 0o644)?, (*uid, *gid), data)
+            }
+            ImageEntry::Dir { mode, uid, gid, .. } => {
+                writer.dir(path, permissions(path, *mode, 0o755)?, (*uid, *gid))
+            }
+            ImageEntry::Nod {
+                device,
+                major,
+                minor,
+                mode,
+                uid,
+                gid,
+                ..
+            } => {
+                let mode = permissions(path, *mode, 0o600)?;
+                writer.device(path, *device, (*major, *minor), mode, (*uid, *gid))
+            }
+            ImageEntry::Symlink {
+                target, uid, gid, ..
+            } => writer.symlink(path, target, (*uid, *gid)),
+        };
+        result.map_err(|e| io_error(path, e))?;
+    }
+    writer.finish().map_err(|e| io_error(TRAILER, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dir(path: &str) -> ImageEntry {
+        ImageEntry::Dir { path: path.to_string(), mode: None, uid: 0, gid: 0 }
+    }
+
+    fn file(path: &str, source: &str) -> ImageEntry {
+        ImageEntry::File {
+            path: path.to_string(),
+            source: source.to_string(),
+            mode: None,
+            uid: 0,
+            gid: 0,
+        }
+    }
+
+    fn sources() -> std::path::PathBuf {
+        let sources = std::env::temp_dir().join(format!("initramfs-{}", std::process::id()));
+        std::fs::create_dir_all(&sources).unwrap();
+        std::fs::write(sources.join("init"), b"#!/bin/sh\n").unwrap();
+        sources
+    }
+
+    fn build_image(entries: Vec<ImageEntry>) -> Result<Vec<u8>, SystemError> {
+        build(&Image { entries }, &sources(), Vec::new())
+    }
+
+    #[test]
+    fn golden_bytes() {
+        let mut writer = Writer::new(Vec::new());
+        writer.dir("/a", 0o755, (0, 0)).unwrap();
+        writer.file("/a/f", 0o640, (1000, 1000), b"hi\n".to_vec()).unwrap();
+        let archive = writer.finish().unwrap();
+
+        let mut expected = Vec::new();
+        expected.extend_from_slice(b"070701");
+        expected.extend_from_slice(b"00000001000041ED000000000000000000000002000000000000000000000000");
+        expected.extend_from_slice(b"00000000000000000000000000000002");
+        expected.extend_from_slice(b"00000000a\0");
+        // Header and name end on a 4 byte boundary, so no padding follows
+        expected.extend_from_slice(b"070701");
+        expected.extend_from_slice(b"00000002000081A0000003E8000003E800000001000000000000000300000000");
+        expected.extend_from_slice(b"00000000000000000000000000000004");
+        expected.extend_from_slice(b"00000000a/f\0\0\0");
+        expected.extend_from_slice(b"hi\n\0");
+        expected.extend_from_slice(b"070701");
+        expected.extend_from_slice(b"0000000000000000000000000000000000000001000000000000000000000000");
+        expected.extend_from_slice(b"0000000000000000000000000000000B");
+        expected.extend_from_slice(b"00000000TRAILER!!!\0\0\0\0");
+        expected.resize(BLOCK_SIZE, 0);
+        assert_eq!(archive, expected);
+    }
+
+    #[test]
+    fn members_and_archive_are_padded() {
+        let mut writer = Writer::new(Vec::new());
+        // 110 byte header with names of 2 to 5 bytes, NUL included
+        for name in ["/a", "/ab", "/abc", "/abcd"] {
+            writer.file(name, 0o644, (0, 0), b"x".to_vec()).unwrap();
+        }
+        let archive = writer.finish().unwrap();
+        assert_eq!(archive.len() % BLOCK_SIZE, 0);
+        let mut offset = 0;
+        for name in ["a", "ab", "abc", "abcd"] {
+            assert_eq!(&archive[offset..offset + 6], MAGIC.as_bytes());
+            let header = 110 + name.len() + 1;
+            assert_eq!(&archive[offset + 110..offset + header - 1], name.as_bytes());
+            let data = (offset + header).next_multiple_of(4);
+            assert_eq!(archive[data], b'x');
+            offset = data + 4;
+        }
+        assert_eq!(&archive[offset..offset + 6], MAGIC.as_bytes());
+        assert_eq!(&archive[offset + 110..offset + 121], b"TRAILER!!!\0");
+        assert!(archive[offset + 121..].iter().all(|b| *b == 0));
+    }
+
+    #[test]
+    fn build_is_deterministic_and_sorted() {
+        let entries = || vec![file("/bin/init", "init"), dir("/bin"), dir("/dev")];
+        let first = build_image(entries()).unwrap();
+        let mut reversed = entries();
+        reversed.reverse();
+        assert_eq!(first, build_image(entries()).unwrap());
+        assert_eq!(first, build_image(reversed).unwrap());
+        // Directories come before their contents whatever the declared order
+        let name = |name: &str| {
+            first.windows(name.len()).position(|w| w == name.as_bytes()).unwrap()
+        };
+        assert!(name("bin\0") < name("bin/init\0"));
+        assert!(name("bin/init\0") < name("dev\0"));
+    }
+
+    #[test]
+    fn build_rejects_invalid_entries() {
+        let message = |entries| build_image(entries).unwrap_err().message;
+        assert!(message(vec![file("/bin/init", "init")]).contains("/bin is not declared as a directory"));
+        assert!(message(vec![file("/init", "init"), dir("/init")]).contains("declared twice"));
+        assert!(message(vec![dir("relative")]).contains("absolute and normalized"));
+        assert!(message(vec![dir("/a/../b")]).contains("absolute and normalized"));
+        assert!(message(vec![dir("/a/")]).contains("absolute and normalized"));
+        let setuid_dir = ImageEntry::Dir { path: "/d".to_string(), mode: Some(0o10755), uid: 0, gid: 0 };
+        assert!(message(vec![setuid_dir]).contains("not a permission mode"));
+        assert!(message(vec![file("/missing", "missing")]).contains("Failed to read"));
+    }
+}
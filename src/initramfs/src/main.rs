@@ -0,0 +1,39 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::PathBuf;
+use system::manifest::Manifest;
+use system::{error, info, SystemError};
+
+// Build the initramfs described by the [image] section of a boot manifest.
+// Usage: mkinitramfs <manifest> <output> [sources]
+// File sources are relative to the sources directory, by default the
+// current one.
+fn run(args: &[String]) -> Result<(), SystemError> {
+    let [manifest, output, rest @ ..] = args else {
+        return Err(SystemError {
+            message: "Usage: mkinitramfs <manifest> <output> [sources]".to_string(),
+        });
+    };
+    let sources = PathBuf::from(rest.first().map(String::as_str).unwrap_or("."));
+    // Unlike init, a missing manifest is an error here
+    if !PathBuf::from(manifest).exists() {
+        return Err(SystemError {
+            message: format!("Manifest {} not found", manifest),
+        });
+    }
+    let image = Manifest::load(manifest)?.image;
+    let file = File::create(output).map_err(|e| SystemError {
+        message: format!("Failed to create {}: {}", output, e),
+    })?;
+    initramfs::build(&image, &sources, BufWriter::new(file))?;
+    info!(entries = image.entries.len(), output = output; "Built initramfs");
+    Ok(())
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Err(e) = run(&args) {
+        error!("{}", e);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,274 @@
+use std::future::Future;
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
+use axum::{
+    extract::{Path, Query, State},
+    http::{header::AUTHORIZATION, Request, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use hyper::server::accept::Accept;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use system::manifest::Admin;
+use system::{error, info, socket_listen, warn, SystemError};
+use tokio::io::unix::AsyncFd;
+use tokio::net::UnixStream;
+use tokio::time::Sleep;
+use crate::{AppState, LOG_CAPACITY};
+
+// Lines returned by the logs endpoint unless ?lines= says otherwise
+const DEFAULT_LOG_LINES: usize = 100;
+// How long accepting pauses when the process is out of resources
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+fn parse_token_hash(hex: &str) -> Result<[u8; 32], SystemError> {
+    let invalid = || SystemError { message: "admin.token_sha256 must be 64 hex digits".to_string() };
+    let hex = hex.trim();
+    if hex.len() != 64 || !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return Err(invalid());
+    }
+    let mut hash = [0u8; 32];
+    for (i, byte) in hash.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).map_err(|_| invalid())?;
+    }
+    Ok(hash)
+}
+
+// Reject requests without `Authorization: Bearer <token>` matching the
+// configured hash. The hashes are compared in constant time.
+async fn authenticate<B>(
+    State(expected): State<Arc<[u8; 32]>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let token = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    let authorized = token.is_some_and(|token| {
+        let actual = Sha256::digest(token.as_bytes());
+        actual.iter().zip(expected.iter()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+    });
+    if !authorized {
+        return (StatusCode::UNAUTHORIZED, "invalid or missing bearer token").into_response();
+    }
+    next.run(req).await
+}
+
+async fn list(State(state): State<AppState>) -> Response {
+    Json(state.services.list()).into_response()
+}
+
+async fn show(State(state): State<AppState>, Path(name): Path<String>) -> Response {
+    match state.services.get(&name) {
+        Some(service) => Json(service).into_response(),
+        None => (StatusCode::NOT_FOUND, format!("unknown service {}", name)).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct LogsQuery {
+    lines: Option<usize>,
+}
+
+async fn logs(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Query(query): Query<LogsQuery>,
+) -> Response {
+    if state.services.get(&name).is_none() && !state.logs.services().contains(&name) {
+        return (StatusCode::NOT_FOUND, format!("unknown service {}", name)).into_response();
+    }
+    let lines = query.lines.unwrap_or(DEFAULT_LOG_LINES).min(LOG_CAPACITY);
+    Json(state.logs.tail(&name, lines)).into_response()
+}
+
+// restart, stop or start a service; the supervisor acts on it asynchronously
+async fn control(State(state): State<AppState>, Path((name, action)): Path<(String, String)>) -> Response {
+    let services = &state.services;
+    let known = match action.as_str() {
+        "restart" => services.restart(&name),
+        "stop" => services.stop(&name),
+        "start" => services.start(&name),
+        _ => return (StatusCode::NOT_FOUND, format!("unknown action {}", action)).into_response(),
+    };
+    if !known {
+        return (StatusCode::NOT_FOUND, format!("unknown service {}", name)).into_response();
+    }
+    info!(service = name, action = action; "Admin request");
+    (StatusCode::ACCEPTED, Json(services.get(&name))).into_response()
+}
+
+// Connections accepted on a vsock listener. Tokio has no vsock type, but a
+// connected vsock socket reads and writes like any other stream socket, so
+// connections are driven through UnixStream. Its address methods do not
+// apply and are never used.
+struct VsockIncoming {
+    listener: AsyncFd<OwnedFd>,
+    // Pause after running out of descriptors or memory, which accepting
+    // again right away would not fix
+    backoff: Option<Pin<Box<Sleep>>>,
+}
+
+impl VsockIncoming {
+    fn bind(port: u32) -> Result<Self, SystemError> {
+        let fd = socket_listen(libc::AF_VSOCK, port, libc::VMADDR_CID_ANY)?;
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        // Supervised services are spawned from this process and must not
+        // inherit the listener
+        let configured = unsafe {
+            let flags = libc::fcntl(fd.as_raw_fd(), libc::F_GETFL);
+            libc::fcntl(fd.as_raw_fd(), libc::F_SETFL, flags | libc::O_NONBLOCK) >= 0
+                && libc::fcntl(fd.as_raw_fd(), libc::F_SETFD, libc::FD_CLOEXEC) >= 0
+        };
+        let listener = configured.then_some(fd).ok_or_else(io::Error::last_os_error);
+        let listener = listener.and_then(AsyncFd::new).map_err(|e| SystemError {
+            message: format!("Failed to register vsock listener on port {}: {}", port, e),
+        })?;
+        Ok(VsockIncoming { listener, backoff: None })
+    }
+}
+
+impl Accept for VsockIncoming {
+    type Conn = UnixStream;
+    type Error = io::Error;
+
+    fn poll_accept(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Conn, Self::Error>>> {
+        use libc::{accept4, SOCK_CLOEXEC, SOCK_NONBLOCK};
+        let this = self.get_mut();
+        loop {
+            if let Some(backoff) = this.backoff.as_mut() {
+                ready!(backoff.as_mut().poll(cx));
+                this.backoff = None;
+            }
+            let mut guard = ready!(this.listener.poll_read_ready(cx))?;
+            let accepted = guard.try_io(|listener| {
+                let (address, length) = (std::ptr::null_mut(), std::ptr::null_mut());
+                let flags = SOCK_NONBLOCK | SOCK_CLOEXEC;
+                match unsafe { accept4(listener.as_raw_fd(), address, length, flags) } {
+                    fd if fd < 0 => Err(io::Error::last_os_error()),
+                    fd => Ok(fd),
+                }
+            });
+            match accepted {
+                Ok(Ok(fd)) => {
+                    let stream = unsafe { std::os::unix::net::UnixStream::from_raw_fd(fd) };
+                    return Poll::Ready(Some(UnixStream::from_std(stream)));
+                }
+                Ok(Err(e)) => match e.raw_os_error().unwrap_or(0) {
+                    libc::EMFILE | libc::ENFILE | libc::ENOBUFS | libc::ENOMEM => {
+                        warn!("Admin API failed to accept, backing off: {}", e);
+                        this.backoff = Some(Box::pin(tokio::time::sleep(ACCEPT_BACKOFF)));
+                    }
+                    // A failed connection must not stop the listener
+                    libc::ECONNABORTED | libc::EINTR | libc::EPROTO | libc::EPERM => {
+                        warn!("Admin API failed to accept: {}", e);
+                    }
+                    _ => return Poll::Ready(Some(Err(e))),
+                },
+                Err(_would_block) => continue,
+            }
+        }
+    }
+}
+
+// Serve the admin API on a vsock port until the listener fails. Does
+// nothing unless the manifest enables it with a token hash.
+pub async fn start_admin(config: Admin, state: AppState) {
+    if !config.enabled {
+        return;
+    }
+    let Some(token_sha256) = &config.token_sha256 else {
+        warn!("Admin API disabled: admin.token_sha256 is not set");
+        return;
+    };
+    let token_hash = match parse_token_hash(token_sha256) {
+        Ok(hash) => Arc::new(hash),
+        Err(e) => {
+            error!("Admin API disabled: {}", e);
+            return;
+        }
+    };
+    let incoming = match VsockIncoming::bind(config.port) {
+        Ok(incoming) => incoming,
+        Err(e) => {
+            error!("Admin API disabled: {}", e);
+            return;
+        }
+    };
+
+    let app = Router::new()
+        .route("/services", get(list))
+        .route("/services/:name", get(show))
+        .route("/services/:name/logs", get(logs))
+        .route("/services/:name/:action", post(control))
+        .route_layer(middleware::from_fn_with_state(token_hash, authenticate))
+        .with_state(state);
+
+    info!(port = config.port; "Admin API listening on vsock");
+    if let Err(e) = axum::Server::builder(incoming).serve(app.into_make_service()).await {
+        error!("Admin API error: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use tower::ServiceExt;
+
+    const TOKEN: &str = "secret";
+
+    fn token_hash() -> [u8; 32] {
+        Sha256::digest(TOKEN.as_bytes()).into()
+    }
+
+    async fn status(authorization: Option<&str>) -> StatusCode {
+        let app = Router::new()
+            .route("/services", get(|| async { "ok" }))
+            .route_layer(middleware::from_fn_with_state(Arc::new(token_hash()), authenticate));
+        let mut request = Request::builder().uri("/services");
+        if let Some(value) = authorization {
+            request = request.header(AUTHORIZATION, value);
+        }
+        app.oneshot(request.body(Body::empty()).unwrap()).await.unwrap().status()
+    }
+
+    #[test]
+    fn token_hash_parses_hex() {
+        let hex: String = token_hash().iter().map(|byte| format!("{:02x}", byte)).collect();
+        assert_eq!(parse_token_hash(&hex).unwrap(), token_hash());
+        assert_eq!(parse_token_hash(&format!(" {}\n", hex.to_uppercase())).unwrap(), token_hash());
+    }
+
+    #[test]
+    fn token_hash_rejects_malformed_hex() {
+        assert!(parse_token_hash("").is_err());
+        assert!(parse_token_hash(&"ab".repeat(31)).is_err());
+        assert!(parse_token_hash(&"ab".repeat(33)).is_err());
+        assert!(parse_token_hash(&"zz".repeat(32)).is_err());
+        assert!(parse_token_hash(&"+1".repeat(32)).is_err());
+        // 64 bytes, but not 64 digits
+        assert!(parse_token_hash(&format!("é{}", "a".repeat(62))).is_err());
+    }
+
+    #[tokio::test]
+    async fn authenticate_requires_bearer_token() {
+        assert_eq!(status(None).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(status(Some(TOKEN)).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(status(Some("Bearer wrong")).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(status(Some("Basic c2VjcmV0")).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(status(Some("Bearer secret")).await, StatusCode::OK);
+    }
+}
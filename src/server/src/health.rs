@@ -0,0 +1,104 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock};
+use axum::{extract::State, http::StatusCode, Json};
+use serde::Serialize;
+use system::boot_time;
+use crate::{redis_ping, AppState};
+
+// Lifecycle of a subsystem as last reported by its owner
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Starting,
+    Up,
+    // Running without a non-critical subsystem
+    Degraded,
+    Down,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Component {
+    pub status: Status,
+    pub detail: String,
+    pub since: String,
+}
+
+// Subsystem state shared between init and the server.
+// Init reports boot and supervision state, the server adds its own checks.
+#[derive(Clone, Default)]
+pub struct Health {
+    components: Arc<RwLock<BTreeMap<String, Component>>>,
+}
+
+impl Health {
+    fn set(&self, name: &str, status: Status, detail: String) {
+        let mut components = self.components.write().expect("!lock");
+        components.insert(name.to_string(), Component {
+            status,
+            detail,
+            since: boot_time(),
+        });
+    }
+
+    // Declare a component so readiness waits for it
+    pub fn register(&self, name: &str) {
+        self.set(name, Status::Starting, String::new());
+    }
+
+    pub fn up(&self, name: &str, detail: impl Into<String>) {
+        self.set(name, Status::Up, detail.into());
+    }
+
+    pub fn degrade(&self, name: &str, detail: impl Into<String>) {
+        self.set(name, Status::Degraded, detail.into());
+    }
+
+    pub fn down(&self, name: &str, detail: impl Into<String>) {
+        self.set(name, Status::Down, detail.into());
+    }
+
+    pub fn snapshot(&self) -> BTreeMap<String, Component> {
+        self.components.read().expect("!lock").clone()
+    }
+}
+
+#[derive(Serialize)]
+pub struct Report {
+    pub ok: bool,
+    pub degraded: bool,
+    pub components: BTreeMap<String, Component>,
+}
+
+fn respond(report: Report) -> (StatusCode, Json<Report>) {
+    let code = if report.ok {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (code, Json(report))
+}
+
+// Liveness: nothing reported by init has gone down. Components that are
+// still starting do not fail liveness.
+pub async fn healthz(State(state): State<AppState>) -> (StatusCode, Json<Report>) {
+    let components = state.health.snapshot();
+    let ok = components.values().all(|c| c.status != Status::Down);
+    let degraded = components.values().any(|c| c.status == Status::Degraded);
+    respond(Report { ok, degraded, components })
+}
+
+// Readiness: every reported component is up or degraded and server
+// dependencies answer
+pub async fn readyz(State(state): State<AppState>) -> (StatusCode, Json<Report>) {
+    let mut components = state.health.snapshot();
+    let redis = match redis_ping(&state.metrics).await {
+        Ok(()) => Component { status: Status::Up, detail: String::new(), since: boot_time() },
+        Err(e) => Component { status: Status::Down, detail: e, since: boot_time() },
+    };
+    components.insert("redis".to_string(), redis);
+    let ok = components
+        .values()
+        .all(|c| matches!(c.status, Status::Up | Status::Degraded));
+    let degraded = components.values().any(|c| c.status == Status::Degraded);
+    respond(Report { ok, degraded, components })
+}
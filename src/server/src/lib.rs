@@ -1,38 +1,159 @@
 use std::net::{SocketAddr, TcpListener};
-use axum::{routing::get, Router};
+use std::sync::{Arc, OnceLock, RwLock};
+use std::time::{Duration, Instant};
+use axum::{
+    extract::State,
+    http::header::{HeaderMap, HeaderName, HeaderValue, CONTENT_TYPE},
+    middleware,
+    routing::get,
+    Router,
+};
 use redis::Commands;
+use system::{debug, error, info, warn, BootReport, SystemError};
 
-async fn access_internet() -> String {
+mod admin;
+mod health;
+mod logs;
+mod metrics;
+mod services;
+mod shutdown;
+pub use admin::start_admin;
+pub use health::{Component, Health, Status};
+pub use logs::{Line, Logs, Stream, LOG_CAPACITY};
+pub use metrics::Metrics;
+pub use services::{ServiceInfo, Services, Target};
+pub use shutdown::Shutdown;
+
+const REDIS_URL: &str = "redis://192.168.127.254:6379";
+// Replaces REDIS_URL, e.g. with a local stand-in when init is emulated
+static REDIS_OVERRIDE: OnceLock<String> = OnceLock::new();
+const REDIS_TIMEOUT: Duration = Duration::from_secs(2);
+// Time in-flight requests get to complete once shutdown is requested
+pub const DRAIN_TIMEOUT: Duration = Duration::from_secs(10);
+
+// State shared by all handlers, handed in by init
+#[derive(Clone, Default)]
+pub struct AppState {
+    pub health: Health,
+    pub metrics: Metrics,
+    pub boot_report: Arc<RwLock<BootReport>>,
+    pub logs: Logs,
+    pub services: Services,
+}
+
+// Point Redis clients at another server. Only the first call has an effect.
+pub fn set_redis_url(url: impl Into<String>) {
+    let _ = REDIS_OVERRIDE.set(url.into());
+}
+
+fn redis_url() -> &'static str {
+    REDIS_OVERRIDE.get().map(String::as_str).unwrap_or(REDIS_URL)
+}
+
+async fn access_internet(State(state): State<AppState>) -> String {
     let url = "http://jsonplaceholder.typicode.com/todos/1";
     let client = reqwest::Client::new();
+    let started = Instant::now();
     let response = client.get(url).send().await;
+    state.metrics.observe_outbound("jsonplaceholder", started, &response);
     match response {
         Ok(res) => res.text().await.unwrap(),
         Err(err) => {
-            eprintln!("{}", err);
-            return Default::default()
+            error!("{}", err);
+            Default::default()
         }
     }
 }
 
-async fn connect_redis() -> String {
-    println!("trying to connect to redis now...");
-    let client = redis::Client::open("redis://192.168.127.254:6379").unwrap();
+async fn connect_redis(State(state): State<AppState>) -> String {
+    debug!("Connecting to Redis");
+    let client = redis::Client::open(redis_url()).unwrap();
     let mut con = client.get_connection().unwrap();
-    let _: () = con.set("my_key", 42).unwrap();
-    
-    let val: String = con.get("my_key").unwrap();
-    val
+    let started = Instant::now();
+    let set = con.set::<_, _, ()>("my_key", 42);
+    state.metrics.observe_redis("SET", started, &set);
+    set.unwrap();
+
+    let started = Instant::now();
+    let val = con.get::<_, String>("my_key");
+    state.metrics.observe_redis("GET", started, &val);
+    val.unwrap()
+}
+
+// Check that Redis accepts connections and answers PING
+pub async fn redis_ping(metrics: &Metrics) -> Result<(), String> {
+    let started = Instant::now();
+    let result = tokio::task::spawn_blocking(|| {
+        let client = redis::Client::open(redis_url()).map_err(|e| e.to_string())?;
+        let mut con = client
+            .get_connection_with_timeout(REDIS_TIMEOUT)
+            .map_err(|e| e.to_string())?;
+        redis::cmd("PING")
+            .query::<String>(&mut con)
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    })
+    .await
+    .map_err(|e| e.to_string())
+    .and_then(|result| result);
+    metrics.observe_redis("PING", started, &result);
+    result
+}
+
+// Ask Redis to persist its dataset so writes made before shutdown survive
+// a restart of the host side
+async fn redis_flush(metrics: &Metrics) -> Result<(), String> {
+    let started = Instant::now();
+    let result = tokio::task::spawn_blocking(|| {
+        let client = redis::Client::open(redis_url()).map_err(|e| e.to_string())?;
+        let mut con = client
+            .get_connection_with_timeout(REDIS_TIMEOUT)
+            .map_err(|e| e.to_string())?;
+        con.set_read_timeout(Some(REDIS_TIMEOUT)).map_err(|e| e.to_string())?;
+        redis::cmd("BGSAVE")
+            .query::<String>(&mut con)
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    })
+    .await
+    .map_err(|e| e.to_string())
+    .and_then(|result| result);
+    metrics.observe_redis("BGSAVE", started, &result);
+    result
+}
+
+// Boot report as JSON. The digest header is the SHA-256 of the exact body,
+// matching the user data of attestations that include the report.
+async fn boot_report(State(state): State<AppState>) -> (HeaderMap, String) {
+    let report = state.boot_report.read().expect("!lock");
+    let digest: String = report.digest().iter().map(|b| format!("{:02x}", b)).collect();
+    let mut headers = HeaderMap::new();
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    headers.insert(
+        HeaderName::from_static("x-boot-report-sha256"),
+        HeaderValue::from_str(&digest).expect("hex is a valid header"),
+    );
+    (headers, report.to_json())
 }
+
 // redis server/
 // policy engine server microservice enclave => signer engine
 
-pub async fn start_server() {
+// Serve until shutdown is triggered, then stop accepting connections and
+// give in-flight requests DRAIN_TIMEOUT to complete before flushing stores.
+// Returns once the server has stopped; errors describe what was cut short.
+pub async fn start_server(state: AppState, shutdown: Shutdown) -> Result<(), SystemError> {
     // Build our application with routes
     let app = Router::new()
         .route("/", get(|| async { "Hello, World!" }))
+        .route("/healthz", get(health::healthz))
+        .route("/readyz", get(health::readyz))
+        .route("/metrics", get(metrics::metrics))
+        .route("/boot-report", get(boot_report))
         .route("/redis", get(connect_redis))
-        .route("/access-internet", get(access_internet));
+        .route("/access-internet", get(access_internet))
+        .route_layer(middleware::from_fn_with_state(state.clone(), metrics::track_requests))
+        .with_state(state.clone());
 
     // Define the address to bind to
     let addr = "0.0.0.0:8000".parse::<SocketAddr>().expect("Invalid address");
@@ -41,18 +162,40 @@ pub async fn start_server() {
     let listener = match TcpListener::bind(addr) {
         Ok(listener) => listener,
         Err(e) => {
-            eprintln!("Failed to bind to address: {}", e);
-            return;
+            error!("Failed to bind to address: {}", e);
+            return Err(SystemError { message: format!("Failed to bind to {}: {}", addr, e) });
         }
     };
 
     // Log successful start
-    println!("Server started on {}", addr);
+    info!("Server started on {}", addr);
 
     // Start the server
-    if let Err(e) = axum::Server::from_tcp(listener).unwrap().serve(app.into_make_service()).await {
-        eprintln!("Server error: {}", e);
+    let server = axum::Server::from_tcp(listener)
+        .unwrap()
+        .serve(app.into_make_service())
+        .with_graceful_shutdown(shutdown.requested());
+    let deadline = async {
+        shutdown.requested().await;
+        tokio::time::sleep(DRAIN_TIMEOUT).await;
+    };
+    let served = tokio::select! {
+        served = server => served.map_err(|e| format!("Server error: {}", e)),
+        // Dropping the server aborts the requests still running
+        _ = deadline => Err(format!("Requests still running after {}s were aborted", DRAIN_TIMEOUT.as_secs())),
+    };
+    if let Err(e) = &served {
+        error!("{}", e);
+    }
+    if !shutdown.is_triggered() {
+        return served.map_err(|message| SystemError { message });
+    }
+    info!("Server stopped accepting connections, flushing stores");
+    let flushed = redis_flush(&state.metrics).await.map_err(|e| format!("Redis flush failed: {}", e));
+    if let Err(e) = &flushed {
+        warn!("{}", e);
     }
+    served.and(flushed).map_err(|message| SystemError { message })
 }
 
 // #[tokio::main]
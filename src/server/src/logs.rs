@@ -0,0 +1,63 @@
+use serde::Serialize;
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+// Lines kept per service
+pub const LOG_CAPACITY: usize = 1000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Stream {
+    Stdout,
+    Stderr,
+    // Notes from init about the stream, e.g. suppressed lines
+    Init,
+}
+
+impl Stream {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Stream::Stdout => "stdout",
+            Stream::Stderr => "stderr",
+            Stream::Init => "init",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Line {
+    // Seconds since boot
+    pub time: f64,
+    pub stream: Stream,
+    pub text: String,
+}
+
+// Recent output of every supervised service, oldest lines dropped first
+#[derive(Clone, Default)]
+pub struct Logs {
+    services: Arc<Mutex<BTreeMap<String, VecDeque<Line>>>>,
+}
+
+impl Logs {
+    pub fn push(&self, service: &str, line: Line) {
+        let mut services = self.services.lock().expect("!lock");
+        let lines = services.entry(service.to_string()).or_default();
+        if lines.len() == LOG_CAPACITY {
+            lines.pop_front();
+        }
+        lines.push_back(line);
+    }
+
+    // Up to `count` of the most recent lines of a service, oldest first
+    pub fn tail(&self, service: &str, count: usize) -> Vec<Line> {
+        let services = self.services.lock().expect("!lock");
+        services
+            .get(service)
+            .map(|lines| lines.iter().skip(lines.len().saturating_sub(count)).cloned().collect())
+            .unwrap_or_default()
+    }
+
+    pub fn services(&self) -> Vec<String> {
+        self.services.lock().expect("!lock").keys().cloned().collect()
+    }
+}
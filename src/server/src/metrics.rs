@@ -0,0 +1,210 @@
+use std::io::Write;
+use std::os::fd::FromRawFd;
+use std::thread;
+use std::time::{Duration, Instant};
+use axum::{
+    extract::{MatchedPath, State},
+    http::Request,
+    middleware::Next,
+    response::Response,
+};
+use prometheus::{
+    Encoder, GaugeVec, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, Opts, Registry,
+    TextEncoder,
+};
+use system::{error, warn, SystemError};
+use crate::AppState;
+
+// Prometheus registry for everything running inside the enclave.
+// Init records boot and supervisor metrics, the server records its own.
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Registry,
+    pub http_requests: IntCounterVec,
+    pub http_duration: HistogramVec,
+    pub redis_commands: IntCounterVec,
+    pub redis_duration: HistogramVec,
+    pub outbound_requests: IntCounterVec,
+    pub outbound_duration: HistogramVec,
+    pub service_restarts: IntCounterVec,
+    pub boot_stage_seconds: GaugeVec,
+    pub entropy_seeded_bytes: IntCounter,
+    pub entropy_seed_failures: IntCounter,
+    pub entropy_reseeds: IntCounterVec,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let registry = Registry::new_custom(Some("enclave".to_string()), None)
+            .expect("valid registry");
+        let counter = |name: &str, help: &str, labels: &[&str]| {
+            let metric = IntCounterVec::new(Opts::new(name, help), labels).expect("valid metric");
+            registry.register(Box::new(metric.clone())).expect("unique metric");
+            metric
+        };
+        let histogram = |name: &str, help: &str, labels: &[&str]| {
+            let metric = HistogramVec::new(HistogramOpts::new(name, help), labels)
+                .expect("valid metric");
+            registry.register(Box::new(metric.clone())).expect("unique metric");
+            metric
+        };
+        let http_requests = counter(
+            "http_requests_total",
+            "HTTP requests handled by the server",
+            &["route", "method", "status"],
+        );
+        let http_duration = histogram(
+            "http_request_duration_seconds",
+            "HTTP request latency",
+            &["route", "method"],
+        );
+        let redis_commands = counter(
+            "redis_commands_total",
+            "Redis round trips by outcome",
+            &["command", "result"],
+        );
+        let redis_duration = histogram(
+            "redis_command_duration_seconds",
+            "Redis round trip latency",
+            &["command"],
+        );
+        let outbound_requests = counter(
+            "outbound_requests_total",
+            "Outbound HTTP calls by outcome",
+            &["target", "result"],
+        );
+        let outbound_duration = histogram(
+            "outbound_request_duration_seconds",
+            "Outbound HTTP call latency",
+            &["target"],
+        );
+        let service_restarts = counter(
+            "service_restarts_total",
+            "Times the init supervisor restarted a service",
+            &["service"],
+        );
+        let entropy_reseeds = counter(
+            "entropy_reseeds_total",
+            "Runs of the entropy reseeder by trigger and outcome",
+            &["trigger", "result"],
+        );
+        let boot_stage_seconds =
+            GaugeVec::new(Opts::new("boot_stage_seconds", "Duration of boot stages"), &["stage"])
+                .expect("valid metric");
+        registry
+            .register(Box::new(boot_stage_seconds.clone()))
+            .expect("unique metric");
+        let entropy_seeded_bytes = IntCounter::new(
+            "entropy_seeded_bytes_total",
+            "Bytes of NSM entropy written to the kernel pool",
+        )
+        .expect("valid metric");
+        registry
+            .register(Box::new(entropy_seeded_bytes.clone()))
+            .expect("unique metric");
+        let entropy_seed_failures = IntCounter::new(
+            "entropy_seed_failures_total",
+            "Failed attempts to seed the kernel pool",
+        )
+        .expect("valid metric");
+        registry
+            .register(Box::new(entropy_seed_failures.clone()))
+            .expect("unique metric");
+
+        Metrics {
+            registry,
+            http_requests,
+            http_duration,
+            redis_commands,
+            redis_duration,
+            outbound_requests,
+            outbound_duration,
+            service_restarts,
+            boot_stage_seconds,
+            entropy_seeded_bytes,
+            entropy_seed_failures,
+            entropy_reseeds,
+        }
+    }
+
+    pub fn observe_redis<T, E>(&self, command: &str, started: Instant, result: &Result<T, E>) {
+        let outcome = if result.is_ok() { "ok" } else { "error" };
+        self.redis_commands.with_label_values(&[command, outcome]).inc();
+        self.redis_duration
+            .with_label_values(&[command])
+            .observe(started.elapsed().as_secs_f64());
+    }
+
+    pub fn observe_outbound<T, E>(&self, target: &str, started: Instant, result: &Result<T, E>) {
+        let outcome = if result.is_ok() { "ok" } else { "error" };
+        self.outbound_requests.with_label_values(&[target, outcome]).inc();
+        self.outbound_duration
+            .with_label_values(&[target])
+            .observe(started.elapsed().as_secs_f64());
+    }
+
+    // Prometheus text exposition of all metrics
+    pub fn render(&self) -> String {
+        let mut buf = Vec::new();
+        if let Err(e) = TextEncoder::new().encode(&self.registry.gather(), &mut buf) {
+            error!("Failed to encode metrics: {}", e);
+        }
+        String::from_utf8(buf).unwrap_or_default()
+    }
+
+    // Send one snapshot of the metrics to a host listener over vsock
+    pub fn push_vsock(&self, cid: u32, port: u32) -> Result<(), SystemError> {
+        use system::socket_connect;
+        let fd = socket_connect(libc::AF_VSOCK, port, cid)?;
+        let mut stream = unsafe { std::fs::File::from_raw_fd(fd) };
+        stream.write_all(self.render().as_bytes()).map_err(|e| SystemError {
+            message: format!("Failed to push metrics: {}", e),
+        })
+    }
+
+    // Push metrics to the host on an interval. Blocks the calling thread.
+    pub fn push_vsock_forever(&self, cid: u32, port: u32, interval: Duration) {
+        loop {
+            if let Err(e) = self.push_vsock(cid, port) {
+                warn!(cid = cid, port = port; "Metrics push failed: {}", e);
+            }
+            thread::sleep(interval);
+        }
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Count and time every request against its route template
+pub async fn track_requests<B>(
+    State(state): State<AppState>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+    let method = req.method().to_string();
+    let started = Instant::now();
+    let response = next.run(req).await;
+    let metrics = &state.metrics;
+    metrics
+        .http_requests
+        .with_label_values(&[&route, &method, response.status().as_str()])
+        .inc();
+    metrics
+        .http_duration
+        .with_label_values(&[&route, &method])
+        .observe(started.elapsed().as_secs_f64());
+    response
+}
+
+pub async fn metrics(State(state): State<AppState>) -> String {
+    state.metrics.render()
+}
@@ -0,0 +1,191 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Instant;
+
+// Whether the supervisor should run a service, changed by the admin API
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Target {
+    Running,
+    Stopped,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ServiceInfo {
+    pub name: String,
+    pub pid: Option<u32>,
+    // Seconds the current instance has been running
+    pub uptime: Option<f64>,
+    pub restarts: u64,
+    pub target: Target,
+}
+
+// Asks the running instance with the given PID to exit
+type Stopper = Arc<dyn Fn(u32) + Send + Sync>;
+
+struct Entry {
+    pid: Option<u32>,
+    started: Option<Instant>,
+    restarts: u64,
+    target: Target,
+    stop: Stopper,
+}
+
+// Supervised services as tracked by init. The supervisor reports starts and
+// exits, the admin API reads them and changes targets.
+#[derive(Clone, Default)]
+pub struct Services {
+    inner: Arc<(Mutex<BTreeMap<String, Entry>>, Condvar)>,
+}
+
+impl Services {
+    pub fn register(&self, name: &str, stop: impl Fn(u32) + Send + Sync + 'static) {
+        let mut services = self.inner.0.lock().expect("!lock");
+        services.insert(name.to_string(), Entry {
+            pid: None,
+            started: None,
+            restarts: 0,
+            target: Target::Running,
+            stop: Arc::new(stop),
+        });
+    }
+
+    pub fn started(&self, name: &str, pid: u32) {
+        let mut services = self.inner.0.lock().expect("!lock");
+        if let Some(entry) = services.get_mut(name) {
+            entry.pid = Some(pid);
+            entry.started = Some(Instant::now());
+        }
+    }
+
+    // Record an exit. Returns whether the service should be started again.
+    pub fn exited(&self, name: &str) -> bool {
+        let mut services = self.inner.0.lock().expect("!lock");
+        match services.get_mut(name) {
+            Some(entry) => {
+                entry.pid = None;
+                entry.started = None;
+                if entry.target == Target::Running {
+                    entry.restarts += 1;
+                }
+                entry.target == Target::Running
+            }
+            None => true,
+        }
+    }
+
+    // Block until the service's target is Running
+    pub fn wait_until_wanted(&self, name: &str) {
+        let (lock, started) = &*self.inner;
+        let services = lock.lock().expect("!lock");
+        let _services = started
+            .wait_while(services, |services| {
+                services.get(name).is_some_and(|entry| entry.target == Target::Stopped)
+            })
+            .expect("!lock");
+    }
+
+    fn info(name: &str, entry: &Entry) -> ServiceInfo {
+        ServiceInfo {
+            name: name.to_string(),
+            pid: entry.pid,
+            uptime: entry.started.map(|started| started.elapsed().as_secs_f64()),
+            restarts: entry.restarts,
+            target: entry.target,
+        }
+    }
+
+    pub fn list(&self) -> Vec<ServiceInfo> {
+        let services = self.inner.0.lock().expect("!lock");
+        services.iter().map(|(name, entry)| Self::info(name, entry)).collect()
+    }
+
+    pub fn get(&self, name: &str) -> Option<ServiceInfo> {
+        let services = self.inner.0.lock().expect("!lock");
+        services.get(name).map(|entry| Self::info(name, entry))
+    }
+
+    // Set the target and stop the running instance, if any. The supervisor
+    // starts a new one when the target is Running. Returns false for
+    // unknown services.
+    fn control(&self, name: &str, target: Target, stop_running: bool) -> bool {
+        let (lock, started) = &*self.inner;
+        let mut services = lock.lock().expect("!lock");
+        let Some(entry) = services.get_mut(name) else {
+            return false;
+        };
+        entry.target = target;
+        let running = entry.pid.filter(|_| stop_running);
+        let stop = entry.stop.clone();
+        drop(services);
+        started.notify_all();
+        if let Some(pid) = running {
+            stop(pid);
+        }
+        true
+    }
+
+    pub fn restart(&self, name: &str) -> bool {
+        self.control(name, Target::Running, true)
+    }
+
+    pub fn stop(&self, name: &str) -> bool {
+        self.control(name, Target::Stopped, true)
+    }
+
+    pub fn start(&self, name: &str) -> bool {
+        self.control(name, Target::Running, false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Services with one running instance, recording the PIDs asked to stop
+    fn running() -> (Services, Arc<Mutex<Vec<u32>>>) {
+        let services = Services::default();
+        let stopped = Arc::new(Mutex::new(Vec::new()));
+        let record = stopped.clone();
+        services.register("vm", move |pid| record.lock().unwrap().push(pid));
+        services.started("vm", 42);
+        (services, stopped)
+    }
+
+    #[test]
+    fn stopped_service_is_not_restarted() {
+        let (services, stopped) = running();
+        assert!(services.stop("vm"));
+        assert_eq!(*stopped.lock().unwrap(), [42]);
+        assert!(!services.exited("vm"));
+        let info = services.get("vm").unwrap();
+        assert_eq!((info.pid, info.restarts, info.target), (None, 0, Target::Stopped));
+    }
+
+    #[test]
+    fn exits_while_running_count_restarts() {
+        let (services, stopped) = running();
+        assert!(services.restart("vm"));
+        assert_eq!(*stopped.lock().unwrap(), [42]);
+        assert!(services.exited("vm"));
+        assert_eq!(services.get("vm").unwrap().restarts, 1);
+    }
+
+    #[test]
+    fn start_does_not_stop_the_running_instance() {
+        let (services, stopped) = running();
+        assert!(services.stop("vm"));
+        assert!(!services.exited("vm"));
+        assert!(services.start("vm"));
+        assert_eq!(*stopped.lock().unwrap(), [42]);
+        assert_eq!(services.get("vm").unwrap().target, Target::Running);
+    }
+
+    #[test]
+    fn control_rejects_unknown_services() {
+        let (services, _) = running();
+        assert!(!services.stop("caddy"));
+        assert!(!services.restart("caddy"));
+    }
+}
@@ -0,0 +1,32 @@
+use std::sync::Arc;
+use tokio::sync::watch;
+
+// Shutdown request shared between init and the servers it starts. Init
+// triggers it once; every clone sees the request.
+#[derive(Clone)]
+pub struct Shutdown {
+    sender: Arc<watch::Sender<bool>>,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Shutdown { sender: Arc::new(watch::channel(false).0) }
+    }
+}
+
+impl Shutdown {
+    pub fn trigger(&self) {
+        self.sender.send_replace(true);
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        *self.sender.borrow()
+    }
+
+    // Resolves once shutdown has been triggered
+    pub async fn requested(&self) {
+        let mut receiver = self.sender.subscribe();
+        // The sender lives as long as self, so waiting cannot fail
+        let _ = receiver.wait_for(|triggered| *triggered).await;
+    }
+}
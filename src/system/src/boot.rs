@@ -0,0 +1,85 @@
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use crate::{info, SystemError};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Outcome {
+    Ok,
+    Failed,
+}
+
+// Timing and result of a single boot stage
+#[derive(Clone, Debug, Serialize)]
+pub struct Stage {
+    pub name: String,
+    // Seconds since boot when the stage started
+    pub start: f64,
+    pub duration_ms: f64,
+    pub outcome: Outcome,
+    pub error: Option<String>,
+}
+
+// Record of everything init did to bring up the enclave
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct BootReport {
+    pub stages: Vec<Stage>,
+}
+
+impl BootReport {
+    // Record the timing and outcome of a stage
+    pub fn record<T>(
+        &mut self,
+        name: &str,
+        start: f64,
+        duration_ms: f64,
+        result: &Result<T, SystemError>,
+    ) {
+        let (outcome, error) = match result {
+            Ok(_) => (Outcome::Ok, None),
+            Err(e) => (Outcome::Failed, Some(e.message.clone())),
+        };
+        self.stages.push(Stage {
+            name: name.to_string(),
+            start,
+            duration_ms,
+            outcome,
+            error,
+        });
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Stage> {
+        self.stages.iter().rev().find(|s| s.name == name)
+    }
+
+    pub fn succeeded(&self) -> bool {
+        self.stages.iter().all(|s| s.outcome == Outcome::Ok)
+    }
+
+    pub fn print(&self) {
+        info!("Boot report:");
+        for s in &self.stages {
+            let outcome = match s.outcome {
+                Outcome::Ok => "ok",
+                Outcome::Failed => "FAILED",
+            };
+            info!(
+                "  {: <12} {: >9.3} ms  {}{}",
+                s.name,
+                s.duration_ms,
+                outcome,
+                s.error.as_ref().map(|e| format!(": {}", e)).unwrap_or_default()
+            );
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("report serializes")
+    }
+
+    // SHA-256 of the JSON report, small enough to bind into attestation
+    // user data while the full report is served separately
+    pub fn digest(&self) -> [u8; 32] {
+        Sha256::digest(self.to_json().as_bytes()).into()
+    }
+}
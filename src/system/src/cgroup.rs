@@ -0,0 +1,94 @@
+use std::fs::{File, OpenOptions};
+use std::path::{Path, PathBuf};
+use crate::manifest::ServiceConfig;
+use crate::SystemError;
+
+// Where init mounts the unified cgroup v2 hierarchy
+pub const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+// Controllers delegated to service cgroups
+pub const CONTROLLERS: [&str; 3] = ["cpu", "memory", "pids"];
+
+// Period cpu.max quotas are expressed against, in microseconds
+const CPU_PERIOD: u64 = 100_000;
+
+fn write(path: &Path, value: &str) -> Result<(), SystemError> {
+    std::fs::write(path, value).map_err(|e| SystemError {
+        message: format!("Failed to write {:?} to {}: {}", value, path.display(), e),
+    })
+}
+
+// Make controllers available to child cgroups of the root
+pub fn enable_controllers(controllers: &[&str]) -> Result<(), SystemError> {
+    let control: Vec<String> = controllers.iter().map(|c| format!("+{}", c)).collect();
+    write(&Path::new(CGROUP_ROOT).join("cgroup.subtree_control"), &control.join(" "))
+}
+
+// A child cgroup of the root, one per supervised service
+#[derive(Clone, Debug)]
+pub struct Cgroup {
+    path: PathBuf,
+}
+
+impl Cgroup {
+    // Create the cgroup, reusing it if it already exists
+    pub fn create(name: &str) -> Result<Self, SystemError> {
+        let path = Path::new(CGROUP_ROOT).join(name);
+        std::fs::create_dir_all(&path).map_err(|e| SystemError {
+            message: format!("Failed to create cgroup {}: {}", path.display(), e),
+        })?;
+        Ok(Cgroup { path })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn set(&self, file: &str, value: &str) -> Result<(), SystemError> {
+        write(&self.path.join(file), value)
+    }
+
+    // Apply the memory, CPU and pids limits of a service. Unset limits are
+    // reset to "max" so removing one from the manifest takes effect.
+    pub fn apply(&self, config: &ServiceConfig) -> Result<(), SystemError> {
+        let max = |value: Option<String>| value.unwrap_or_else(|| "max".to_string());
+        self.set("memory.max", &max(config.memory_max.clone()))?;
+        self.set("memory.high", &max(config.memory_high.clone()))?;
+        self.set("pids.max", &max(config.pids_max.map(|n| n.to_string())))?;
+        let quota = config
+            .cpu_percent
+            .map(|percent| (CPU_PERIOD * percent as u64 / 100).to_string());
+        self.set("cpu.max", &format!("{} {}", max(quota), CPU_PERIOD))
+    }
+
+    // cgroup.procs opened for writing. A child that writes "0" to it before
+    // exec joins the cgroup without a window where it runs outside of it.
+    pub fn procs(&self) -> Result<File, SystemError> {
+        let path = self.path.join("cgroup.procs");
+        OpenOptions::new().write(true).open(&path).map_err(|e| SystemError {
+            message: format!("Failed to open {}: {}", path.display(), e),
+        })
+    }
+
+    // Processes currently in the cgroup
+    pub fn pids(&self) -> Result<Vec<i32>, SystemError> {
+        let path = self.path.join("cgroup.procs");
+        let procs = std::fs::read_to_string(&path).map_err(|e| SystemError {
+            message: format!("Failed to read {}: {}", path.display(), e),
+        })?;
+        Ok(procs.lines().filter_map(|line| line.trim().parse().ok()).collect())
+    }
+
+    // Send a signal to every process in the cgroup
+    pub fn signal(&self, signal: i32) -> Result<(), SystemError> {
+        for pid in self.pids()? {
+            unsafe { libc::kill(pid, signal) };
+        }
+        Ok(())
+    }
+
+    // SIGKILL every process in the cgroup, including ones forked meanwhile
+    pub fn kill(&self) -> Result<(), SystemError> {
+        self.set("cgroup.kill", "1")
+    }
+}
@@ -0,0 +1,156 @@
+use std::str::FromStr;
+use crate::log::Level;
+use crate::SystemError;
+
+// Only arguments under this namespace are interpreted by init
+const PREFIX: &str = "enclave.";
+
+// Boot options passed as `enclave.*` kernel command line arguments
+#[derive(Debug, Default)]
+pub struct BootOptions {
+    // enclave.debug[=bool]: extra diagnostics on the console
+    pub debug: bool,
+    // enclave.selftest[=bool]: overrides selftest.enabled in the manifest
+    pub selftest: Option<bool>,
+    // enclave.loglevel=error|warn|info|debug|trace
+    pub loglevel: Option<Level>,
+    // enclave.manifest=<path>: alternative boot manifest
+    pub manifest: Option<String>,
+    // enclave.metrics_port=<port>: push metrics to this host vsock port
+    pub metrics_port: Option<u32>,
+    // Unknown keys and invalid values, reported but otherwise ignored
+    pub warnings: Vec<String>,
+}
+
+fn parse_bool(value: Option<&str>) -> Result<bool, String> {
+    match value.map(|v| v.to_ascii_lowercase()).as_deref() {
+        None | Some("1") | Some("true") | Some("yes") | Some("on") => Ok(true),
+        Some("0") | Some("false") | Some("no") | Some("off") => Ok(false),
+        Some(v) => Err(format!("invalid boolean: {}", v)),
+    }
+}
+
+fn required(value: Option<&str>) -> Result<&str, String> {
+    match value {
+        Some(v) if !v.is_empty() => Ok(v),
+        _ => Err("missing value".to_string()),
+    }
+}
+
+// Split a command line into arguments the way the kernel does:
+// whitespace separated, with double quotes grouping spaces.
+fn split(cmdline: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut arg = String::new();
+    let mut quoted = false;
+    for c in cmdline.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => {
+                if !arg.is_empty() {
+                    args.push(std::mem::take(&mut arg));
+                }
+            }
+            c => arg.push(c),
+        }
+    }
+    if !arg.is_empty() {
+        args.push(arg);
+    }
+    args
+}
+
+impl BootOptions {
+    pub fn parse(cmdline: &str) -> BootOptions {
+        let mut options = BootOptions::default();
+        for arg in split(cmdline) {
+            let Some(option) = arg.strip_prefix(PREFIX) else {
+                continue;
+            };
+            let (key, value) = match option.split_once('=') {
+                Some((key, value)) => (key, Some(value)),
+                None => (option, None),
+            };
+            let result = match key {
+                "debug" => parse_bool(value).map(|v| options.debug = v),
+                "selftest" => parse_bool(value).map(|v| options.selftest = Some(v)),
+                "loglevel" => required(value)
+                    .and_then(Level::from_str)
+                    .map(|v| options.loglevel = Some(v)),
+                "manifest" => required(value).map(|v| options.manifest = Some(v.to_string())),
+                "metrics_port" => required(value)
+                    .and_then(|v| v.parse().map_err(|_| format!("invalid port: {}", v)))
+                    .map(|v| options.metrics_port = Some(v)),
+                _ => Err("unknown option".to_string()),
+            };
+            if let Err(e) = result {
+                options.warnings.push(format!("{}{}: {}", PREFIX, key, e));
+            }
+        }
+        options
+    }
+
+    // Parse the running kernel's command line. Requires /proc.
+    pub fn load() -> Result<BootOptions, SystemError> {
+        match std::fs::read_to_string("/proc/cmdline") {
+            Ok(cmdline) => Ok(BootOptions::parse(&cmdline)),
+            Err(e) => Err(SystemError {
+                message: format!("Failed to read /proc/cmdline: {}", e),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quoted_values_keep_spaces() {
+        assert_eq!(
+            split(r#"console=ttyS0 enclave.manifest="/etc/my manifest.toml"  quiet"#),
+            ["console=ttyS0", "enclave.manifest=/etc/my manifest.toml", "quiet"]
+        );
+        let options = BootOptions::parse(r#"enclave.manifest="/etc/my manifest.toml""#);
+        assert_eq!(options.manifest.as_deref(), Some("/etc/my manifest.toml"));
+        assert!(options.warnings.is_empty());
+    }
+
+    #[test]
+    fn bare_and_explicit_booleans() {
+        assert!(BootOptions::parse("enclave.debug").debug);
+        assert!(BootOptions::parse("enclave.debug=yes").debug);
+        assert!(!BootOptions::parse("enclave.debug=0").debug);
+        assert!(!BootOptions::parse("enclave.debug=off").debug);
+        assert_eq!(BootOptions::parse("enclave.selftest=false").selftest, Some(false));
+        assert_eq!(BootOptions::parse("").selftest, None);
+
+        let options = BootOptions::parse("enclave.debug=maybe");
+        assert!(!options.debug);
+        assert_eq!(options.warnings, ["enclave.debug: invalid boolean: maybe"]);
+    }
+
+    #[test]
+    fn invalid_values_are_warnings() {
+        let options = BootOptions::parse("enclave.loglevel=loud enclave.metrics_port=http enclave.manifest=");
+        assert!(options.loglevel.is_none());
+        assert!(options.metrics_port.is_none());
+        assert!(options.manifest.is_none());
+        assert_eq!(options.warnings.len(), 3);
+        assert!(options.warnings[0].starts_with("enclave.loglevel: "));
+        assert_eq!(options.warnings[1], "enclave.metrics_port: invalid port: http");
+        assert_eq!(options.warnings[2], "enclave.manifest: missing value");
+
+        let options = BootOptions::parse("enclave.loglevel=debug enclave.metrics_port=9001");
+        assert_eq!(options.loglevel, Some(Level::Debug));
+        assert_eq!(options.metrics_port, Some(9001));
+        assert!(options.warnings.is_empty());
+    }
+
+    #[test]
+    fn unknown_keys_are_warnings() {
+        let options = BootOptions::parse("root=/dev/ram0 enclave.verbose=1 enclave.debug");
+        assert!(options.debug);
+        assert_eq!(options.warnings, ["enclave.verbose: unknown option"]);
+    }
+}
@@ -2,31 +2,46 @@ use libc::{ c_ulong, c_int, c_void };
 use std::{
     mem::{zeroed, size_of},
     ffi::CString,
-    fs::File,
     os::unix::io::AsRawFd,
     fmt,
+    time::Duration,
 };
 
+pub mod boot;
+pub mod cgroup;
+pub mod cmdline;
+pub mod log;
+pub mod manifest;
+pub mod module;
+pub mod mounts;
+pub mod sandbox;
+pub use boot::BootReport;
+pub use cmdline::BootOptions;
+pub use manifest::Manifest;
+
+#[derive(Debug)]
 pub struct SystemError {
     pub message: String,
 }
 impl fmt::Display for SystemError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{} {}", boot_time(), self.message)
+        write!(f, "{}", self.message)
     }
 }
+impl std::error::Error for SystemError {}
 
-// Log dmesg formatted log to console
-pub fn dmesg(message: String){
-    println!("{} {}", boot_time(), message);
+// Time since boot, including time spent suspended
+pub fn uptime() -> Duration {
+    use libc::{clock_gettime, timespec, CLOCK_BOOTTIME};
+    let mut t = timespec { tv_sec: 0, tv_nsec: 0 };
+    unsafe { clock_gettime(CLOCK_BOOTTIME, &mut t as *mut timespec); }
+    Duration::new(t.tv_sec as u64, t.tv_nsec as u32)
 }
 
 // Dmesg formatted seconds since boot
 pub fn boot_time() -> String {
-    use libc::{clock_gettime, timespec, CLOCK_BOOTTIME};
-    let mut t = timespec { tv_sec: 0, tv_nsec: 0 };
-    unsafe { clock_gettime(CLOCK_BOOTTIME, &mut t as *mut timespec); }
-    format!("[ {: >4}.{}]", t.tv_sec, t.tv_nsec / 1000).to_string()
+    let t = uptime();
+    format!("[ {: >4}.{:06}]", t.as_secs(), t.subsec_micros())
 }
 
 // Unconditionally reboot the system now
@@ -37,6 +52,22 @@ pub fn reboot(){
     }
 }
 
+// Unconditionally halt the system now, leaving the console readable
+pub fn halt(){
+    use libc::{reboot, RB_HALT_SYSTEM};
+    unsafe {
+        reboot(RB_HALT_SYSTEM);
+    }
+}
+
+// Unconditionally power off the system now
+pub fn poweroff(){
+    use libc::{reboot, RB_POWER_OFF};
+    unsafe {
+        reboot(RB_POWER_OFF);
+    }
+}
+
 // libc::mount casting/error wrapper
 pub fn mount(
     src: &str,
@@ -46,10 +77,13 @@ pub fn mount(
     data: &str,
 ) -> Result<(), SystemError> {
     use libc::mount;
-    let src_cs = CString::new(src).unwrap();
-    let fstype_cs = CString::new(fstype).unwrap();
-    let data_cs = CString::new(data).unwrap();
-    let target_cs = CString::new(target).unwrap();
+    let cstring = |s: &str| CString::new(s).map_err(|_| SystemError {
+        message: format!("Failed to mount {}: NUL byte in argument", target)
+    });
+    let src_cs = cstring(src)?;
+    let fstype_cs = cstring(fstype)?;
+    let data_cs = cstring(data)?;
+    let target_cs = cstring(target)?;
     if unsafe {
         mount(
             src_cs.as_ptr(),
@@ -59,7 +93,9 @@ pub fn mount(
             data_cs.as_ptr() as *const c_void
         )
     } != 0 {
-        Err(SystemError { message: format!("Failed to mount: {}", target) })
+        Err(SystemError {
+            message: format!("Failed to mount {}: {}", target, std::io::Error::last_os_error())
+        })
     } else {
         Ok(())
     }
@@ -78,7 +114,7 @@ pub fn freopen(
         freopen(
             filename_cs.as_ptr(),
             mode_cs.as_ptr(),
-            fdopen(file, mode_cs.as_ptr() as *const i8)
+            fdopen(file, mode_cs.as_ptr())
         )
     }.is_null() {
         Err(SystemError { message: format!("Failed to freopen: {}", filename) })
@@ -89,15 +125,26 @@ pub fn freopen(
 
 // Insert kernel module into memory
 pub fn insmod(path: &str) -> Result<(), SystemError> {
-    use libc::{syscall, SYS_finit_module};
-    let file = File::open(path).unwrap();
-    let fd = file.as_raw_fd();
-    if unsafe { syscall(SYS_finit_module, fd, &[0u8; 1], 0) } < 0 {
-        Err(SystemError {
-            message: format!("Failed to insert kernel module: {}", path)
-        })
-    } else {
-        Ok(())
+    let module = module::Module {
+        path: path.to_string(),
+        params: String::new(),
+        sha256: None,
+    };
+    module::load(&module).map(|_| ())
+}
+
+// Check if a network interface exists and is administratively up
+pub fn interface_up(name: &str) -> Result<bool, SystemError> {
+    use libc::IFF_UP;
+    let flags = match std::fs::read_to_string(format!("/sys/class/net/{}/flags", name)) {
+        Ok(flags) => flags,
+        Err(_) => return Ok(false),
+    };
+    match i32::from_str_radix(flags.trim().trim_start_matches("0x"), 16) {
+        Ok(flags) => Ok(flags & IFF_UP != 0),
+        Err(_) => Err(SystemError {
+            message: format!("Failed to parse flags of interface: {}", name)
+        }),
     }
 }
 
@@ -120,8 +167,44 @@ pub fn socket_connect(
             size_of::<sockaddr_vm>() as _,
         )
     } < 0 {
+        let error = std::io::Error::last_os_error();
+        unsafe { libc::close(fd) };
+        Err(SystemError {
+            message: format!("Failed to connect to socket {}:{}: {}", cid, port, error)
+        })
+    } else {
+        Ok(fd)
+    }
+}
+
+// Listening stream socket, the counterpart of socket_connect
+pub fn socket_listen(
+    family: c_int,
+    port: u32,
+    cid: u32,
+) -> Result<c_int, SystemError> {
+    use libc::{bind, listen, socket, sockaddr, sockaddr_vm, SOCK_STREAM};
+    let fd = unsafe { socket(family, SOCK_STREAM, 0) };
+    if fd < 0 {
+        return Err(SystemError {
+            message: format!("Failed to create socket: {}", std::io::Error::last_os_error())
+        });
+    }
+    if unsafe {
+        let mut sa: sockaddr_vm = zeroed();
+        sa.svm_family = family as _;
+        sa.svm_port = port;
+        sa.svm_cid = cid;
+        bind(
+            fd,
+            &sa as *const _ as *mut sockaddr,
+            size_of::<sockaddr_vm>() as _,
+        ) < 0 || listen(fd, 16) < 0
+    } {
+        let error = std::io::Error::last_os_error();
+        unsafe { libc::close(fd) };
         Err(SystemError {
-            message: format!("Failed to connect to socket: {}", family)
+            message: format!("Failed to listen on socket {}:{}: {}", cid, port, error)
         })
     } else {
         Ok(fd)
@@ -132,14 +215,16 @@ pub fn socket_connect(
 pub fn seed_entropy(
     size: usize,
     source: fn(usize) -> Result<Vec<u8>, SystemError>,
+    credit: bool,
 ) -> Result<usize, SystemError> {
-    use std::io::Write;
-
-	let entropy_sample = match source(size) {
-        Ok(sample)=> sample,
-        Err(e)=> { return Err(e) },
-    };
+	let entropy_sample = source(size)?;
+    add_entropy(&entropy_sample, credit)?;
+    Ok(entropy_sample.len())
+}
 
+// Mix a sample into the kernel randomness pool, crediting it if asked.
+pub fn add_entropy(sample: &[u8], credit: bool) -> Result<(), SystemError> {
+    use std::io::Write;
 	use std::fs::OpenOptions;
     let mut random_fd = match OpenOptions::new()
         .read(true)
@@ -158,12 +243,91 @@ pub fn seed_entropy(
     // The RNDADDENTROPY crediting system is now complexity with no gain.
     // We just simply write samples to /dev/urandom now.
     // See: https://cdn.kernel.org/pub/linux/kernel/v5.x/ChangeLog-5.10.119
-	match random_fd.write_all(&entropy_sample) {
-        Ok(()) => Ok(entropy_sample.len()),
-        Err(_) => {
-    	    return Err(SystemError {
-		    	message: String::from("Failed to write to /dev/urandom"),
-		    });
+    if !credit {
+        return random_fd.write_all(sample).map_err(|_| SystemError {
+            message: String::from("Failed to write to /dev/urandom"),
+        });
+    }
+
+    // Older kernels only count entropy added through RNDADDENTROPY, which
+    // takes a struct rand_pool_info { int entropy_count; int buf_size; buf }
+    const RNDADDENTROPY: c_ulong = 0x4008_5203;
+    let bits = (sample.len() * 8) as c_int;
+    let mut info = Vec::with_capacity(2 * size_of::<c_int>() + sample.len());
+    info.extend_from_slice(&bits.to_ne_bytes());
+    info.extend_from_slice(&(sample.len() as c_int).to_ne_bytes());
+    info.extend_from_slice(sample);
+    if unsafe {
+        libc::ioctl(random_fd.as_raw_fd(), RNDADDENTROPY as _, info.as_ptr())
+    } < 0 {
+        Err(SystemError {
+            message: format!(
+                "Failed to credit entropy: {}",
+                std::io::Error::last_os_error()
+            ),
+        })
+    } else {
+        Ok(())
+    }
+}
+
+// Block until the kernel CRNG is initialized, as reported by a non-blocking
+// getrandom. Returns how long the wait took.
+pub fn wait_for_crng(timeout: Option<Duration>) -> Result<Duration, SystemError> {
+    use libc::{getrandom, EAGAIN, EINTR, GRND_NONBLOCK};
+    let started = std::time::Instant::now();
+    let mut byte = [0u8; 1];
+    loop {
+        if unsafe { getrandom(byte.as_mut_ptr() as *mut c_void, 1, GRND_NONBLOCK) } >= 0 {
+            return Ok(started.elapsed());
+        }
+        let error = std::io::Error::last_os_error();
+        match error.raw_os_error() {
+            Some(EAGAIN) | Some(EINTR) => {}
+            _ => {
+                return Err(SystemError {
+                    message: format!("Failed to query CRNG state: {}", error),
+                })
+            }
+        }
+        if timeout.is_some_and(|timeout| started.elapsed() >= timeout) {
+            return Err(SystemError {
+                message: format!(
+                    "CRNG not initialized after {}s",
+                    started.elapsed().as_secs()
+                ),
+            });
         }
-	}
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+// Release of the running kernel as (major, minor)
+pub fn kernel_version() -> Result<(u32, u32), SystemError> {
+    use libc::{uname, utsname};
+    let mut name: utsname = unsafe { zeroed() };
+    if unsafe { uname(&mut name) } < 0 {
+        return Err(SystemError { message: String::from("Failed to call uname") });
+    }
+    let release = unsafe { std::ffi::CStr::from_ptr(name.release.as_ptr()) };
+    let release = release.to_string_lossy();
+    let mut parts = release
+        .split(|c: char| !c.is_ascii_digit())
+        .map(|p| p.parse::<u32>());
+    match (parts.next(), parts.next()) {
+        (Some(Ok(major)), Some(Ok(minor))) => Ok((major, minor)),
+        _ => Err(SystemError {
+            message: format!("Failed to parse kernel release: {}", release),
+        }),
+    }
+}
+
+// Whether entropy should be credited with RNDADDENTROPY on this kernel
+pub fn credit_entropy(mode: manifest::EntropyCredit) -> bool {
+    use manifest::EntropyCredit;
+    match mode {
+        EntropyCredit::Always => true,
+        EntropyCredit::Never => false,
+        EntropyCredit::Auto => matches!(kernel_version(), Ok(version) if version < (5, 10)),
+    }
 }
@@ -0,0 +1,193 @@
+use std::fmt::{self, Display, Write as _};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Mutex;
+use crate::boot_time;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl FromStr for Level {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "error" | "3" => Ok(Level::Error),
+            "warn" | "warning" | "4" => Ok(Level::Warn),
+            "info" | "6" => Ok(Level::Info),
+            "debug" | "7" => Ok(Level::Debug),
+            "trace" | "8" => Ok(Level::Trace),
+            _ => Err(format!("invalid log level: {}", s)),
+        }
+    }
+}
+
+impl Level {
+    fn name(self) -> &'static str {
+        match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+            Level::Trace => "TRACE",
+        }
+    }
+
+    // Syslog priority used for /dev/kmsg records
+    fn priority(self) -> u8 {
+        match self {
+            Level::Error => 3,
+            Level::Warn => 4,
+            Level::Info => 6,
+            Level::Debug | Level::Trace => 7,
+        }
+    }
+}
+
+// Most verbose level that is written, Info until init reads the cmdline
+static LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+// Opened by init once /dev is mounted
+static KMSG: Mutex<Option<File>> = Mutex::new(None);
+// Records with a lower kmsg priority are echoed to the console by the
+// kernel, as read from kernel.printk when kmsg is opened
+static CONSOLE_LOGLEVEL: AtomicU8 = AtomicU8::new(0);
+// Used when kernel.printk cannot be read, as in the kernel
+const DEFAULT_CONSOLE_LOGLEVEL: u8 = 7;
+
+// Longest kmsg record the kernel accepts without truncating it
+const KMSG_MAX: usize = 976;
+
+// First field of kernel.printk: the current console loglevel
+fn console_loglevel(printk: &str) -> Option<u8> {
+    printk.split_whitespace().next()?.parse().ok()
+}
+
+// Set the level and start copying records to the kernel log
+pub fn init(level: Level) {
+    set_level(level);
+    match OpenOptions::new().write(true).open("/dev/kmsg") {
+        Ok(kmsg) => {
+            let console = std::fs::read_to_string("/proc/sys/kernel/printk")
+                .ok()
+                .and_then(|printk| console_loglevel(&printk))
+                .unwrap_or(DEFAULT_CONSOLE_LOGLEVEL);
+            CONSOLE_LOGLEVEL.store(console, Ordering::Relaxed);
+            *KMSG.lock().expect("!lock") = Some(kmsg);
+        }
+        Err(e) => crate::warn!("Logging to the console only: /dev/kmsg: {}", e),
+    }
+}
+
+pub fn set_level(level: Level) {
+    LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+pub fn enabled(level: Level) -> bool {
+    level as u8 <= LEVEL.load(Ordering::Relaxed)
+}
+
+// key=value, quoted when the value would not read back as a single token
+fn push_field(out: &mut String, key: &str, value: &dyn Display) {
+    let value = value.to_string();
+    if value.is_empty() || value.contains(|c: char| c.is_whitespace() || c == '"' || c == '=') {
+        let _ = write!(out, " {}={:?}", key, value);
+    } else {
+        let _ = write!(out, " {}={}", key, value);
+    }
+}
+
+// Write one record. Use the level macros instead of calling this directly.
+pub fn write(level: Level, target: &str, message: fmt::Arguments, fields: &[(&str, &dyn Display)]) {
+    let mut record = message.to_string();
+    for (key, value) in fields {
+        push_field(&mut record, key, *value);
+    }
+    let mut kmsg = KMSG.lock().expect("!lock");
+    // Records the kernel echoes are not written to the console again
+    let echoed = kmsg.is_some() && level.priority() < CONSOLE_LOGLEVEL.load(Ordering::Relaxed);
+    if !echoed {
+        let line = format!("{} {:<5} {}: {}", boot_time(), level.name(), target, record);
+        if level <= Level::Warn {
+            eprintln!("{}", line);
+        } else {
+            println!("{}", line);
+        }
+    }
+    if let Some(kmsg) = kmsg.as_mut() {
+        // The kernel adds its own timestamp
+        let mut entry = format!("<{}>{}: {}", level.priority(), target, record);
+        if entry.len() > KMSG_MAX {
+            let mut end = KMSG_MAX;
+            while !entry.is_char_boundary(end) {
+                end -= 1;
+            }
+            entry.truncate(end);
+        }
+        entry.push('\n');
+        let _ = kmsg.write_all(entry.as_bytes());
+    }
+}
+
+// log!(level, "format", args) or log!(level, key = value, ...; "format", args)
+#[macro_export]
+macro_rules! log {
+    ($level:expr, $($key:ident = $value:expr),+ ; $($arg:tt)+) => {
+        if $crate::log::enabled($level) {
+            $crate::log::write(
+                $level,
+                module_path!(),
+                format_args!($($arg)+),
+                &[$((stringify!($key), &$value as &dyn ::std::fmt::Display)),+],
+            );
+        }
+    };
+    ($level:expr, $($arg:tt)+) => {
+        if $crate::log::enabled($level) {
+            $crate::log::write($level, module_path!(), format_args!($($arg)+), &[]);
+        }
+    };
+}
+
+#[macro_export]
+macro_rules! error {
+    ($($arg:tt)+) => { $crate::log!($crate::log::Level::Error, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)+) => { $crate::log!($crate::log::Level::Warn, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)+) => { $crate::log!($crate::log::Level::Info, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)+) => { $crate::log!($crate::log::Level::Debug, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! trace {
+    ($($arg:tt)+) => { $crate::log!($crate::log::Level::Trace, $($arg)+) };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_console_loglevel() {
+        assert_eq!(console_loglevel("4\t4\t1\t7\n"), Some(4));
+        assert_eq!(console_loglevel("7 4 1 7"), Some(7));
+        assert_eq!(console_loglevel(""), None);
+    }
+}
@@ -0,0 +1,356 @@
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use crate::module::Module;
+use crate::SystemError;
+
+// Default location of the boot manifest inside the initramfs
+pub const MANIFEST_PATH: &str = "/manifest.toml";
+
+// Boot manifest describing how init brings up the enclave
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Manifest {
+    pub boot: Boot,
+    // Kernel modules loaded by the platform stage, in order
+    pub modules: Vec<Module>,
+    pub entropy: Entropy,
+    // Settings of supervised services, by service name
+    pub services: BTreeMap<String, ServiceConfig>,
+    pub selftest: SelfTest,
+    pub attestation: Attestation,
+    pub broker: Broker,
+    pub admin: Admin,
+    pub image: Image,
+}
+
+impl Default for Manifest {
+    fn default() -> Self {
+        Manifest {
+            boot: Boot::default(),
+            modules: vec![Module {
+                path: "/nsm.ko".to_string(),
+                params: String::new(),
+                sha256: None,
+            }],
+            entropy: Entropy::default(),
+            services: BTreeMap::new(),
+            selftest: SelfTest::default(),
+            attestation: Attestation::default(),
+            broker: Broker::default(),
+            admin: Admin::default(),
+            image: Image::default(),
+        }
+    }
+}
+
+// What init does when a critical boot stage fails
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FailureAction {
+    Halt,
+    Reboot,
+    Continue,
+}
+
+// Boot failure policy
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Boot {
+    pub on_critical_failure: FailureAction,
+    // Stages whose failure triggers on_critical_failure. Failures of any
+    // other stage leave the enclave running in a degraded state.
+    pub critical: Vec<String>,
+}
+
+impl Default for Boot {
+    fn default() -> Self {
+        Boot {
+            on_critical_failure: FailureAction::Reboot,
+            critical: ["manifest", "rootfs", "platform", "entropy", "crng"].map(String::from).to_vec(),
+        }
+    }
+}
+
+impl Boot {
+    pub fn is_critical(&self, stage: &str) -> bool {
+        self.critical.iter().any(|s| s == stage)
+    }
+}
+
+// How NSM entropy is mixed into the kernel pool
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EntropyCredit {
+    // Credit only on kernels older than 5.10
+    Auto,
+    Always,
+    Never,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Entropy {
+    // Bytes seeded at boot
+    pub boot_bytes: usize,
+    // Bytes mixed in on every reseed
+    pub reseed_bytes: usize,
+    // Seconds between reseeds, 0 disables periodic reseeding
+    pub reseed_interval_secs: u64,
+    pub credit: EntropyCredit,
+    // How long services may be held back waiting for the kernel CRNG
+    pub crng_timeout_secs: u64,
+}
+
+impl Default for Entropy {
+    fn default() -> Self {
+        Entropy {
+            boot_bytes: 4096,
+            reseed_bytes: 256,
+            reseed_interval_secs: 300,
+            credit: EntropyCredit::Auto,
+            crng_timeout_secs: 30,
+        }
+    }
+}
+
+// Resource limits, sandbox and start order of a supervised service. The
+// server inside init is ordered as the service "server"; only `after` and
+// `ready` apply to it.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServiceConfig {
+    // memory.max, bytes with an optional K, M or G suffix
+    pub memory_max: Option<String>,
+    // memory.high, where the kernel starts throttling and reclaiming
+    pub memory_high: Option<String>,
+    // Share of one CPU the service may use, may exceed 100
+    pub cpu_percent: Option<u32>,
+    // Maximum number of processes and threads
+    pub pids_max: Option<u64>,
+    // Numeric uid and gid to run as, gid defaults to the uid
+    pub user: Option<u32>,
+    pub group: Option<u32>,
+    // Capabilities kept in the bounding and ambient sets, e.g.
+    // ["net_bind_service"]. Unset leaves capabilities untouched.
+    pub capabilities: Option<Vec<String>>,
+    pub no_new_privs: bool,
+    // Syscalls that fail with EPERM
+    pub seccomp_deny: Vec<String>,
+    // Private mount namespace, which also hides /dev/nsm
+    pub mount_namespace: bool,
+    // Private PID namespace, with its own /proc if mount_namespace is set
+    pub pid_namespace: bool,
+    // Services that must be ready before this one starts
+    pub after: Vec<String>,
+    // Checks that must all pass before the service counts as ready
+    pub ready: Vec<Probe>,
+    // How long dependents wait for the service to become ready
+    pub ready_timeout_secs: Option<u64>,
+}
+
+// Readiness check of a service
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum Probe {
+    // A TCP connection to address succeeds
+    Tcp { address: String },
+    // A network interface is up
+    Interface { name: String },
+    // A file or socket exists
+    File { path: String },
+    // GET a URL and expect a status code
+    Http {
+        url: String,
+        #[serde(default = "default_http_status")]
+        status: u16,
+    },
+}
+
+// Claims init binds into the attestation documents it requests
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Attestation {
+    // Use the SHA-256 of the boot report as user data
+    pub include_boot_report: bool,
+}
+
+// Local socket through which services use the NSM device owned by init
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Broker {
+    pub enabled: bool,
+    pub socket: String,
+    // PCRs services may extend. Locking PCRs is never allowed.
+    pub allowed_pcrs: Vec<u16>,
+    // Largest random sample a single request may ask for
+    pub max_random_bytes: usize,
+}
+
+impl Default for Broker {
+    fn default() -> Self {
+        Broker {
+            enabled: true,
+            socket: "/run/nsm.sock".to_string(),
+            allowed_pcrs: Vec::new(),
+            max_random_bytes: 4096,
+        }
+    }
+}
+
+// Operator API for supervised services, reachable only over vsock
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Admin {
+    pub enabled: bool,
+    pub port: u32,
+    // Hex SHA-256 of the bearer token. The API stays off until it is set,
+    // so the token itself never has to be part of the image.
+    pub token_sha256: Option<String>,
+}
+
+impl Default for Admin {
+    fn default() -> Self {
+        Admin { enabled: true, port: 9100, token_sha256: None }
+    }
+}
+
+// Contents of the initramfs, built by mkinitramfs. Init itself does not
+// read this section.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Image {
+    pub entries: Vec<ImageEntry>,
+}
+
+// One archive member. Permissions default to 0644 for files, 0755 for
+// directories, 0600 for device nodes and 0777 for symlinks; owners to root.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum ImageEntry {
+    // Regular file copied from source, relative to the build directory
+    File {
+        path: String,
+        source: String,
+        mode: Option<u32>,
+        #[serde(default)]
+        uid: u32,
+        #[serde(default)]
+        gid: u32,
+    },
+    Dir {
+        path: String,
+        mode: Option<u32>,
+        #[serde(default)]
+        uid: u32,
+        #[serde(default)]
+        gid: u32,
+    },
+    // Character ("c") or block ("b") device node
+    Nod {
+        path: String,
+        device: DeviceKind,
+        major: u32,
+        minor: u32,
+        mode: Option<u32>,
+        #[serde(default)]
+        uid: u32,
+        #[serde(default)]
+        gid: u32,
+    },
+    Symlink {
+        path: String,
+        target: String,
+        #[serde(default)]
+        uid: u32,
+        #[serde(default)]
+        gid: u32,
+    },
+}
+
+impl ImageEntry {
+    pub fn path(&self) -> &str {
+        match self {
+            ImageEntry::File { path, .. }
+            | ImageEntry::Dir { path, .. }
+            | ImageEntry::Nod { path, .. }
+            | ImageEntry::Symlink { path, .. } => path,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+pub enum DeviceKind {
+    #[serde(rename = "c")]
+    Char,
+    #[serde(rename = "b")]
+    Block,
+}
+
+// Checks run once services are started
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SelfTest {
+    pub enabled: bool,
+    // Reboot instead of continuing when a check fails
+    pub fail_boot: bool,
+    // How long each check may keep retrying before it fails
+    pub timeout_secs: u64,
+    pub checks: Vec<Check>,
+}
+
+impl Default for SelfTest {
+    fn default() -> Self {
+        SelfTest {
+            enabled: false,
+            fail_boot: false,
+            timeout_secs: 30,
+            checks: Vec::new(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum Check {
+    // GET a URL and expect a status code
+    Http {
+        url: String,
+        #[serde(default = "default_http_status")]
+        status: u16,
+    },
+    // Resolve a hostname to at least one address
+    Dns { host: String },
+    // PING the Redis instance used by the server
+    Redis,
+    // Request an attestation document from the NSM
+    Attestation,
+}
+
+fn default_http_status() -> u16 {
+    200
+}
+
+impl Manifest {
+    // Settings of a service, defaults if the manifest has none
+    pub fn service(&self, name: &str) -> ServiceConfig {
+        self.services.get(name).cloned().unwrap_or_default()
+    }
+
+    // Load a manifest, treating a missing file as an empty manifest
+    pub fn load(path: &str) -> Result<Manifest, SystemError> {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(Manifest::default())
+            }
+            Err(e) => {
+                return Err(SystemError {
+                    message: format!("Failed to read manifest {}: {}", path, e),
+                })
+            }
+        };
+        toml::from_str(&text).map_err(|e| SystemError {
+            message: format!("Failed to parse manifest {}: {}", path, e),
+        })
+    }
+}
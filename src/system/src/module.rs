@@ -0,0 +1,96 @@
+use std::ffi::CString;
+use std::io::Read;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use crate::{info, SystemError};
+
+// Kernel module loaded at boot, in the order listed in the manifest.
+// Dependencies must be listed before the modules that use them.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Module {
+    // .ko, .ko.xz or .ko.zst
+    pub path: String,
+    // Parameters as passed to insmod, e.g. "debug=1 size=4"
+    #[serde(default)]
+    pub params: String,
+    // Hex SHA-256 of the file as stored, checked before loading
+    pub sha256: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Loaded {
+    Inserted,
+    // The kernel already had the module, which counts as success
+    AlreadyLoaded,
+}
+
+fn error(path: &str, message: impl std::fmt::Display) -> SystemError {
+    SystemError {
+        message: format!("Failed to load kernel module {}: {}", path, message),
+    }
+}
+
+fn verify(path: &str, data: &[u8], expected: &str) -> Result<(), SystemError> {
+    let actual: String = Sha256::digest(data)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    if actual.eq_ignore_ascii_case(expected.trim()) {
+        Ok(())
+    } else {
+        Err(error(path, format!("SHA-256 {} does not match {}", actual, expected)))
+    }
+}
+
+// Module image with any compression named by the extension removed
+fn decompress(path: &str, data: Vec<u8>) -> Result<Vec<u8>, SystemError> {
+    if path.ends_with(".xz") {
+        let mut image = Vec::new();
+        lzma_rs::xz_decompress(&mut data.as_slice(), &mut image)
+            .map_err(|e| error(path, format!("xz: {}", e)))?;
+        Ok(image)
+    } else if path.ends_with(".zst") {
+        use ruzstd::streaming_decoder::StreamingDecoder;
+        let mut image = Vec::new();
+        let mut source = data.as_slice();
+        StreamingDecoder::new(&mut source)
+            .map_err(|e| error(path, format!("zstd: {}", e)))?
+            .read_to_end(&mut image)
+            .map_err(|e| error(path, format!("zstd: {}", e)))?;
+        Ok(image)
+    } else {
+        Ok(data)
+    }
+}
+
+pub fn load(module: &Module) -> Result<Loaded, SystemError> {
+    use libc::{syscall, SYS_init_module, EEXIST};
+    let path = module.path.as_str();
+    let data = std::fs::read(path).map_err(|e| error(path, e))?;
+    if let Some(expected) = &module.sha256 {
+        verify(path, &data, expected)?;
+    }
+    let image = decompress(path, data)?;
+    let params = CString::new(module.params.as_str())
+        .map_err(|_| error(path, "parameters contain a NUL byte"))?;
+    if unsafe { syscall(SYS_init_module, image.as_ptr(), image.len(), params.as_ptr()) } < 0 {
+        let e = std::io::Error::last_os_error();
+        return match e.raw_os_error() {
+            Some(EEXIST) => Ok(Loaded::AlreadyLoaded),
+            _ => Err(error(path, e)),
+        };
+    }
+    Ok(Loaded::Inserted)
+}
+
+// Load modules in order, stopping at the first failure
+pub fn load_all(modules: &[Module]) -> Result<(), SystemError> {
+    for module in modules {
+        match load(module)? {
+            Loaded::Inserted => info!(path = module.path; "Loaded kernel module"),
+            Loaded::AlreadyLoaded => info!(path = module.path; "Kernel module already loaded"),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,253 @@
+use libc::c_ulong;
+use std::path::Path;
+use crate::{error, info, mount, SystemError};
+
+// One line of /proc/self/mountinfo
+#[derive(Clone, Debug)]
+pub struct MountInfo {
+    pub target: String,
+    pub fstype: String,
+    pub source: String,
+    pub options: String,
+}
+
+// Undo the octal escaping the kernel applies to spaces and friends
+fn unescape(field: &str) -> String {
+    let bytes = field.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let octal = bytes.get(i + 1..i + 4).filter(|d| d.iter().all(|d| (b'0'..=b'7').contains(d)));
+        if let (b'\\', Some(digits)) = (bytes[i], octal) {
+            out.push(digits.iter().fold(0u8, |code, d| code.wrapping_mul(8) + (d - b'0')));
+            i += 4;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+fn parse_line(line: &str) -> Option<MountInfo> {
+    // id parent major:minor root target options [optional...] - fstype source super
+    let (left, right) = line.split_once(" - ")?;
+    let left: Vec<&str> = left.split(' ').collect();
+    let mut right = right.split(' ');
+    Some(MountInfo {
+        target: unescape(left.get(4)?),
+        options: left.get(5)?.to_string(),
+        fstype: right.next()?.to_string(),
+        source: unescape(right.next()?),
+    })
+}
+
+// Current mounts, empty when /proc is not mounted yet
+pub fn mountinfo() -> Vec<MountInfo> {
+    std::fs::read_to_string("/proc/self/mountinfo")
+        .map(|text| text.lines().filter_map(parse_line).collect())
+        .unwrap_or_default()
+}
+
+// Topmost mount on target, if anything is mounted there
+pub fn mounted(target: &str) -> Option<MountInfo> {
+    let target = target.trim_end_matches('/');
+    let target = if target.is_empty() { "/" } else { target };
+    mountinfo().into_iter().rev().find(|m| m.target == target)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Applied {
+    Mounted,
+    Remounted,
+    // Something was already mounted on the target
+    Skipped,
+}
+
+// Entry of a mount table
+#[derive(Clone, Debug)]
+pub struct Mount {
+    pub source: String,
+    pub target: String,
+    pub fstype: String,
+    pub flags: c_ulong,
+    pub data: String,
+    // Change the flags of an existing mount instead of mounting
+    pub remount: bool,
+}
+
+impl Mount {
+    pub fn new(source: &str, target: &str, fstype: &str) -> Self {
+        Mount {
+            source: source.to_string(),
+            target: target.to_string(),
+            fstype: fstype.to_string(),
+            flags: 0,
+            data: String::new(),
+            remount: false,
+        }
+    }
+
+    pub fn tmpfs(target: &str) -> Self {
+        Mount::new("tmpfs", target, "tmpfs")
+    }
+
+    pub fn bind(source: &str, target: &str) -> Self {
+        Mount::new(source, target, "").flags(libc::MS_BIND)
+    }
+
+    // Adjust flags or options of what is mounted on target
+    pub fn remount(target: &str) -> Self {
+        Mount {
+            remount: true,
+            ..Mount::new("", target, "")
+        }
+    }
+
+    pub fn flags(mut self, flags: c_ulong) -> Self {
+        self.flags |= flags;
+        self
+    }
+
+    // Add a comma separated filesystem option
+    pub fn option(mut self, option: &str) -> Self {
+        if !self.data.is_empty() {
+            self.data.push(',');
+        }
+        self.data.push_str(option);
+        self
+    }
+
+    pub fn read_only(self) -> Self {
+        self.flags(libc::MS_RDONLY)
+    }
+
+    // tmpfs size limit, in bytes or with a k/m/g suffix or %
+    pub fn size(self, size: &str) -> Self {
+        self.option(&format!("size={}", size))
+    }
+
+    fn is_bind(&self) -> bool {
+        self.flags & libc::MS_BIND != 0
+    }
+
+    // Create the mountpoint, a file when bind mounting a file
+    fn create_target(&self) -> Result<(), SystemError> {
+        let target = Path::new(&self.target);
+        if target.exists() {
+            return Ok(());
+        }
+        let result = if self.is_bind() && Path::new(&self.source).is_file() {
+            target
+                .parent()
+                .map_or(Ok(()), std::fs::create_dir_all)
+                .and_then(|()| std::fs::File::create(target).map(|_| ()))
+        } else {
+            std::fs::create_dir_all(target)
+        };
+        result.map_err(|e| SystemError {
+            message: format!("Failed to create mountpoint {}: {}", self.target, e),
+        })
+    }
+
+    // Mount unless something is already mounted on the target
+    pub fn apply(&self) -> Result<Applied, SystemError> {
+        use libc::MS_REMOUNT;
+        if self.remount {
+            mount("", &self.target, "", self.flags | MS_REMOUNT, &self.data)?;
+            return Ok(Applied::Remounted);
+        }
+        if mounted(&self.target).is_some() {
+            return Ok(Applied::Skipped);
+        }
+        self.create_target()?;
+        mount(&self.source, &self.target, &self.fstype, self.flags, &self.data)?;
+        // The kernel ignores most flags on the initial bind mount
+        let extra = self.flags & !libc::MS_BIND;
+        if self.is_bind() && extra != 0 {
+            mount("", &self.target, "", libc::MS_BIND | MS_REMOUNT | extra, "")?;
+        }
+        Ok(Applied::Mounted)
+    }
+}
+
+// Apply every entry in order, continuing past failures so one bad entry
+// does not leave the rest of the table unmounted
+pub fn apply_all(table: &[Mount]) -> Result<(), SystemError> {
+    let mut failed = Vec::new();
+    for entry in table {
+        match entry.apply() {
+            Ok(Applied::Mounted) => info!(target = entry.target, fstype = entry.fstype; "Mounted"),
+            Ok(Applied::Remounted) => info!(target = entry.target; "Remounted"),
+            Ok(Applied::Skipped) => info!(target = entry.target; "Already mounted"),
+            Err(e) => {
+                error!(target = entry.target; "{}", e);
+                failed.push(entry.target.as_str());
+            }
+        }
+    }
+    if failed.is_empty() {
+        Ok(())
+    } else {
+        Err(SystemError { message: format!("Failed to mount: {}", failed.join(", ")) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unescapes_octal_sequences() {
+        assert_eq!(unescape(r"/mnt/my\040disk"), "/mnt/my disk");
+        assert_eq!(unescape(r"tab\011and\134backslash"), "tab\tand\\backslash");
+        assert_eq!(unescape(r"/new\012line"), "/new\nline");
+        // Not an escape: too short or not octal
+        assert_eq!(unescape(r"/a\04"), r"/a\04");
+        assert_eq!(unescape(r"/a\089"), r"/a\089");
+        assert_eq!(unescape(r"/trailing\"), r"/trailing\");
+    }
+
+    #[test]
+    fn parses_lines_without_optional_fields() {
+        let line = "22 1 0:21 / /proc rw,nosuid,nodev,noexec,relatime - proc proc rw";
+        let info = parse_line(line).unwrap();
+        assert_eq!(info.target, "/proc");
+        assert_eq!(info.options, "rw,nosuid,nodev,noexec,relatime");
+        assert_eq!(info.fstype, "proc");
+        assert_eq!(info.source, "proc");
+    }
+
+    #[test]
+    fn parses_lines_with_optional_fields() {
+        let line = "36 35 98:0 /mnt1 /mnt2 rw,noatime master:1 - ext3 /dev/root rw,errors=continue";
+        let info = parse_line(line).unwrap();
+        assert_eq!(info.target, "/mnt2");
+        assert_eq!(info.options, "rw,noatime");
+        assert_eq!(info.fstype, "ext3");
+        assert_eq!(info.source, "/dev/root");
+
+        let line = "29 1 259:2 / / rw,relatime shared:1 master:2 propagate_from:3 - xfs /dev/nvme0n1p1 rw,attr2";
+        let info = parse_line(line).unwrap();
+        assert_eq!(info.target, "/");
+        assert_eq!(info.fstype, "xfs");
+        assert_eq!(info.source, "/dev/nvme0n1p1");
+    }
+
+    #[test]
+    fn parses_escaped_targets_and_sources() {
+        let line = r"412 29 0:52 / /media/usb\040stick rw,nosuid shared:210 - vfat /dev/sdb\0401 rw,fmask=0022";
+        let info = parse_line(line).unwrap();
+        assert_eq!(info.target, "/media/usb stick");
+        assert_eq!(info.source, "/dev/sdb 1");
+        assert_eq!(info.fstype, "vfat");
+    }
+
+    #[test]
+    fn rejects_malformed_lines() {
+        assert!(parse_line("").is_none());
+        assert!(parse_line("22 1 0:21 / /proc rw proc proc rw").is_none());
+        assert!(parse_line("22 1 0:21 / - proc proc rw").is_none());
+        assert!(parse_line("22 1 0:21 / /proc rw - ").is_none());
+    }
+}
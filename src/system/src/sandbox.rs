@@ -0,0 +1,405 @@
+use libc::{c_int, c_ulong, gid_t, sock_filter, uid_t};
+use std::io::Error;
+use std::sync::atomic::{AtomicI32, Ordering};
+use crate::manifest::ServiceConfig;
+use crate::SystemError;
+
+// Capability names in the order of their numbers in linux/capability.h
+const CAPABILITIES: [&str; 41] = [
+    "chown", "dac_override", "dac_read_search", "fowner", "fsetid", "kill", "setgid",
+    "setuid", "setpcap", "linux_immutable", "net_bind_service", "net_broadcast",
+    "net_admin", "net_raw", "ipc_lock", "ipc_owner", "sys_module", "sys_rawio",
+    "sys_chroot", "sys_ptrace", "sys_pacct", "sys_admin", "sys_boot", "sys_nice",
+    "sys_resource", "sys_time", "sys_tty_config", "mknod", "lease", "audit_write",
+    "audit_control", "setfcap", "mac_override", "mac_admin", "syslog", "wake_alarm",
+    "block_suspend", "audit_read", "perfmon", "bpf", "checkpoint_restore",
+];
+
+// Syscalls that can be named in a seccomp deny list
+const SYSCALLS: &[(&str, libc::c_long)] = &[
+    ("acct", libc::SYS_acct),
+    ("add_key", libc::SYS_add_key),
+    ("bpf", libc::SYS_bpf),
+    ("chroot", libc::SYS_chroot),
+    ("clock_settime", libc::SYS_clock_settime),
+    ("delete_module", libc::SYS_delete_module),
+    ("finit_module", libc::SYS_finit_module),
+    ("init_module", libc::SYS_init_module),
+    ("kexec_file_load", libc::SYS_kexec_file_load),
+    ("kexec_load", libc::SYS_kexec_load),
+    ("keyctl", libc::SYS_keyctl),
+    ("mount", libc::SYS_mount),
+    ("open_by_handle_at", libc::SYS_open_by_handle_at),
+    ("perf_event_open", libc::SYS_perf_event_open),
+    ("personality", libc::SYS_personality),
+    ("pivot_root", libc::SYS_pivot_root),
+    ("process_vm_readv", libc::SYS_process_vm_readv),
+    ("process_vm_writev", libc::SYS_process_vm_writev),
+    ("ptrace", libc::SYS_ptrace),
+    ("reboot", libc::SYS_reboot),
+    ("request_key", libc::SYS_request_key),
+    ("setns", libc::SYS_setns),
+    ("settimeofday", libc::SYS_settimeofday),
+    ("swapoff", libc::SYS_swapoff),
+    ("swapon", libc::SYS_swapon),
+    ("umount2", libc::SYS_umount2),
+    ("unshare", libc::SYS_unshare),
+    ("userfaultfd", libc::SYS_userfaultfd),
+];
+
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: u32 = 0xC000_003E;
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH: u32 = 0xC000_00B7;
+
+// Classic BPF opcodes used by the seccomp filter
+const BPF_LD_W_ABS: u16 = 0x20;
+const BPF_JEQ_K: u16 = 0x15;
+#[cfg(target_arch = "x86_64")]
+const BPF_JGE_K: u16 = 0x35;
+const BPF_RET_K: u16 = 0x06;
+// Offsets into struct seccomp_data
+const SECCOMP_DATA_NR: u32 = 0;
+const SECCOMP_DATA_ARCH: u32 = 4;
+// x32 syscalls share the x86_64 audit arch and are told apart by this bit
+#[cfg(target_arch = "x86_64")]
+const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+
+const CAPABILITY_VERSION_3: u32 = 0x2008_0522;
+
+// Device the sandbox hides from services with their own mount namespace
+const NSM_DEVICE: &std::ffi::CStr = c"/dev/nsm";
+
+fn capability(name: &str) -> Result<u32, SystemError> {
+    let name = name.to_ascii_lowercase();
+    let name = name.strip_prefix("cap_").unwrap_or(&name);
+    CAPABILITIES
+        .iter()
+        .position(|c| *c == name)
+        .map(|n| n as u32)
+        .ok_or_else(|| SystemError { message: format!("Unknown capability: {}", name) })
+}
+
+fn syscall(name: &str) -> Result<libc::c_long, SystemError> {
+    SYSCALLS
+        .iter()
+        .find(|(n, _)| *n == name)
+        .map(|(_, nr)| *nr)
+        .ok_or_else(|| SystemError { message: format!("Unsupported syscall in seccomp list: {}", name) })
+}
+
+fn statement(code: u16, k: u32) -> sock_filter {
+    sock_filter { code, jt: 0, jf: 0, k }
+}
+
+fn jump(k: u32, jt: u8, jf: u8) -> sock_filter {
+    sock_filter { code: BPF_JEQ_K, jt, jf, k }
+}
+
+// Filter failing the listed syscalls with EPERM and killing the process
+// on a foreign architecture or ABI
+fn deny_filter(numbers: &[libc::c_long]) -> Vec<sock_filter> {
+    use libc::{EPERM, SECCOMP_RET_ALLOW, SECCOMP_RET_ERRNO, SECCOMP_RET_KILL_PROCESS};
+    let mut filter = vec![
+        statement(BPF_LD_W_ABS, SECCOMP_DATA_ARCH),
+        jump(AUDIT_ARCH, 1, 0),
+        statement(BPF_RET_K, SECCOMP_RET_KILL_PROCESS),
+        statement(BPF_LD_W_ABS, SECCOMP_DATA_NR),
+    ];
+    // Otherwise the x32 numbers of denied syscalls would get through
+    #[cfg(target_arch = "x86_64")]
+    filter.extend([
+        sock_filter { code: BPF_JGE_K, jt: 0, jf: 1, k: X32_SYSCALL_BIT },
+        statement(BPF_RET_K, SECCOMP_RET_KILL_PROCESS),
+    ]);
+    for nr in numbers {
+        filter.push(jump(*nr as u32, 0, 1));
+        filter.push(statement(BPF_RET_K, SECCOMP_RET_ERRNO | EPERM as u32));
+    }
+    filter.push(statement(BPF_RET_K, SECCOMP_RET_ALLOW));
+    filter
+}
+
+// Child the intermediate process of a PID namespace waits for
+static NAMESPACE_INIT: AtomicI32 = AtomicI32::new(0);
+
+extern "C" fn forward_signal(signal: c_int) {
+    unsafe {
+        libc::kill(NAMESPACE_INIT.load(Ordering::Relaxed), signal);
+    }
+}
+
+fn check(result: c_int) -> std::io::Result<()> {
+    if result < 0 {
+        Err(Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+// Restrictions a supervised service runs under, prepared in the parent so
+// that entering them after fork needs no allocation
+#[derive(Clone, Default)]
+pub struct Sandbox {
+    uid: Option<uid_t>,
+    gid: Option<gid_t>,
+    // Bitmask of capabilities kept in the bounding and ambient sets
+    capabilities: Option<u64>,
+    last_capability: u32,
+    no_new_privs: bool,
+    filter: Vec<sock_filter>,
+    mount_namespace: bool,
+    pid_namespace: bool,
+}
+
+impl Sandbox {
+    pub fn new(config: &ServiceConfig) -> Result<Self, SystemError> {
+        let capabilities = match &config.capabilities {
+            Some(names) => Some(
+                names
+                    .iter()
+                    .map(|name| capability(name))
+                    .try_fold(0u64, |mask, cap| cap.map(|cap| mask | 1 << cap))?,
+            ),
+            None => None,
+        };
+        let numbers = config
+            .seccomp_deny
+            .iter()
+            .map(|name| syscall(name))
+            .collect::<Result<Vec<_>, _>>()?;
+        let last_capability = std::fs::read_to_string("/proc/sys/kernel/cap_last_cap")
+            .ok()
+            .and_then(|s| s.trim().parse().ok())
+            .unwrap_or(CAPABILITIES.len() as u32 - 1);
+        Ok(Sandbox {
+            uid: config.user,
+            gid: config.group.or(config.user),
+            capabilities,
+            last_capability,
+            // Installing a filter without CAP_SYS_ADMIN requires no_new_privs
+            no_new_privs: config.no_new_privs || !numbers.is_empty(),
+            filter: if numbers.is_empty() { Vec::new() } else { deny_filter(&numbers) },
+            mount_namespace: config.mount_namespace,
+            pid_namespace: config.pid_namespace,
+        })
+    }
+
+    fn namespaces(&self) -> std::io::Result<()> {
+        use libc::{mount, unshare, CLONE_NEWNS, CLONE_NEWPID, MS_BIND, MS_PRIVATE, MS_REC};
+        let mut flags = 0;
+        if self.mount_namespace {
+            flags |= CLONE_NEWNS;
+        }
+        if self.pid_namespace {
+            flags |= CLONE_NEWPID;
+        }
+        check(unsafe { unshare(flags) })?;
+        if self.mount_namespace {
+            let (null, no_data) = (std::ptr::null(), std::ptr::null());
+            check(unsafe { mount(null, c"/".as_ptr(), null, MS_REC | MS_PRIVATE, no_data) })?;
+            // The NSM device may be absent, so a failed mask is not an error
+            unsafe {
+                mount(c"/dev/null".as_ptr(), NSM_DEVICE.as_ptr(), null, MS_BIND, no_data);
+            }
+        }
+        Ok(())
+    }
+
+    // The new PID namespace only applies to children, so fork once more.
+    // The intermediate process forwards termination requests, waits and
+    // mirrors the exit status, with no more privileges than the service.
+    fn enter_pid_namespace(&self) -> std::io::Result<()> {
+        use libc::{
+            _exit, fork, mount, prctl, sigaction, sigemptyset, waitpid, EINTR,
+            MS_NODEV, MS_NOEXEC, MS_NOSUID, PR_SET_PDEATHSIG, SIGINT, SIGKILL, SIGTERM,
+            WEXITSTATUS, WIFEXITED, WIFSIGNALED, WTERMSIG,
+        };
+        let pid = unsafe { fork() };
+        if pid < 0 {
+            return Err(Error::last_os_error());
+        }
+        if pid > 0 {
+            unsafe {
+                // Do not hold the spawn status pipe or other descriptors. The
+                // libc crate only wraps close_range for glibc.
+                libc::syscall(libc::SYS_close_range, 3, u32::MAX, 0);
+                NAMESPACE_INIT.store(pid, Ordering::Relaxed);
+                let mut action: sigaction = std::mem::zeroed();
+                action.sa_sigaction = forward_signal as extern "C" fn(c_int) as usize;
+                sigemptyset(&mut action.sa_mask);
+                for signal in [SIGTERM, SIGINT] {
+                    sigaction(signal, &action, std::ptr::null_mut());
+                }
+                if self.drop_privileges().is_err() {
+                    libc::kill(pid, SIGKILL);
+                    _exit(1);
+                }
+                let mut status = 0;
+                while waitpid(pid, &mut status, 0) < 0 {
+                    if Error::last_os_error().raw_os_error() != Some(EINTR) {
+                        _exit(1);
+                    }
+                }
+                if WIFEXITED(status) {
+                    _exit(WEXITSTATUS(status));
+                }
+                _exit(if WIFSIGNALED(status) { 128 + WTERMSIG(status) } else { 1 });
+            }
+        }
+        check(unsafe { prctl(PR_SET_PDEATHSIG, SIGKILL as c_ulong) })?;
+        if self.mount_namespace {
+            let flags = MS_NOSUID | MS_NODEV | MS_NOEXEC;
+            check(unsafe {
+                mount(
+                    c"proc".as_ptr(),
+                    c"/proc".as_ptr(),
+                    c"proc".as_ptr(),
+                    flags,
+                    std::ptr::null(),
+                )
+            })?;
+        }
+        Ok(())
+    }
+
+    // Become the service's user with no capabilities at all, for a process
+    // that never executes anything
+    fn drop_privileges(&self) -> std::io::Result<()> {
+        use libc::{prctl, setgroups, setresgid, setresuid, syscall, SYS_capset, PR_SET_NO_NEW_PRIVS};
+        if let Some(gid) = self.gid {
+            check(unsafe { setgroups(0, std::ptr::null()) })?;
+            check(unsafe { setresgid(gid, gid, gid) })?;
+        }
+        if let Some(uid) = self.uid {
+            check(unsafe { setresuid(uid, uid, uid) })?;
+        }
+        // Empty effective, permitted and inheritable sets, which also
+        // empties the ambient set
+        let header = [CAPABILITY_VERSION_3, 0];
+        let data = [0u32; 6];
+        check(unsafe { syscall(SYS_capset, header.as_ptr(), data.as_ptr()) } as c_int)?;
+        check(unsafe { prctl(PR_SET_NO_NEW_PRIVS, 1 as c_ulong, 0, 0, 0) })
+    }
+
+    fn drop_capabilities(&self, keep: u64) -> std::io::Result<()> {
+        use libc::{prctl, PR_CAPBSET_DROP};
+        for cap in 0..=self.last_capability {
+            if keep & (1 << cap) == 0 {
+                check(unsafe { prctl(PR_CAPBSET_DROP, cap as c_ulong) })?;
+            }
+        }
+        Ok(())
+    }
+
+    fn switch_user(&self) -> std::io::Result<()> {
+        use libc::{prctl, setgroups, setresgid, setresuid, PR_SET_KEEPCAPS};
+        if self.capabilities.is_some() {
+            check(unsafe { prctl(PR_SET_KEEPCAPS, 1 as c_ulong) })?;
+        }
+        if let Some(gid) = self.gid {
+            check(unsafe { setgroups(0, std::ptr::null()) })?;
+            check(unsafe { setresgid(gid, gid, gid) })?;
+        }
+        if let Some(uid) = self.uid {
+            check(unsafe { setresuid(uid, uid, uid) })?;
+        }
+        Ok(())
+    }
+
+    // Make the kept capabilities survive exec by raising them as ambient
+    fn raise_ambient(&self, keep: u64) -> std::io::Result<()> {
+        use libc::{prctl, syscall, SYS_capset, PR_CAP_AMBIENT, PR_CAP_AMBIENT_RAISE};
+        let header = [CAPABILITY_VERSION_3, 0];
+        let (low, high) = (keep as u32, (keep >> 32) as u32);
+        // effective, permitted, inheritable for each 32 bit half
+        let data = [low, low, low, high, high, high];
+        check(unsafe { syscall(SYS_capset, header.as_ptr(), data.as_ptr()) } as c_int)?;
+        for cap in 0..=self.last_capability {
+            if keep & (1 << cap) != 0 {
+                check(unsafe {
+                    prctl(PR_CAP_AMBIENT, PR_CAP_AMBIENT_RAISE as c_ulong, cap as c_ulong, 0, 0)
+                })?;
+            }
+        }
+        Ok(())
+    }
+
+    // Apply the sandbox to the current process. Meant for pre_exec: it only
+    // makes async-signal-safe calls and changes the calling process for good.
+    pub fn enter(&self) -> std::io::Result<()> {
+        use libc::{prctl, sock_fprog, PR_SET_NO_NEW_PRIVS, PR_SET_SECCOMP, SECCOMP_MODE_FILTER};
+        if self.mount_namespace || self.pid_namespace {
+            self.namespaces()?;
+        }
+        if self.pid_namespace {
+            self.enter_pid_namespace()?;
+        }
+        if let Some(keep) = self.capabilities {
+            self.drop_capabilities(keep)?;
+        }
+        self.switch_user()?;
+        if let Some(keep) = self.capabilities {
+            self.raise_ambient(keep)?;
+        }
+        if self.no_new_privs {
+            check(unsafe { prctl(PR_SET_NO_NEW_PRIVS, 1 as c_ulong, 0, 0, 0) })?;
+        }
+        if !self.filter.is_empty() {
+            let program = sock_fprog {
+                len: self.filter.len() as u16,
+                filter: self.filter.as_ptr() as *mut sock_filter,
+            };
+            check(unsafe {
+                prctl(PR_SET_SECCOMP, SECCOMP_MODE_FILTER as c_ulong, &program as *const sock_fprog)
+            })?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libc::{EPERM, SECCOMP_RET_ALLOW, SECCOMP_RET_ERRNO, SECCOMP_RET_KILL_PROCESS};
+
+    // Run the filter on a syscall the way the kernel would
+    fn run(filter: &[sock_filter], arch: u32, nr: u32) -> u32 {
+        let (mut pc, mut accumulator) = (0, 0);
+        loop {
+            let op = filter[pc];
+            pc += 1;
+            let taken = match op.code {
+                BPF_LD_W_ABS => {
+                    accumulator = if op.k == SECCOMP_DATA_ARCH { arch } else { nr };
+                    continue;
+                }
+                BPF_RET_K => return op.k,
+                BPF_JEQ_K => accumulator == op.k,
+                #[cfg(target_arch = "x86_64")]
+                BPF_JGE_K => accumulator >= op.k,
+                code => panic!("unexpected opcode {:#x}", code),
+            };
+            pc += if taken { op.jt } else { op.jf } as usize;
+        }
+    }
+
+    #[test]
+    fn deny_filter_fails_listed_syscalls() {
+        let filter = deny_filter(&[libc::SYS_mount, libc::SYS_ptrace]);
+        let denied = SECCOMP_RET_ERRNO | EPERM as u32;
+        assert_eq!(run(&filter, AUDIT_ARCH, libc::SYS_mount as u32), denied);
+        assert_eq!(run(&filter, AUDIT_ARCH, libc::SYS_ptrace as u32), denied);
+        assert_eq!(run(&filter, AUDIT_ARCH, libc::SYS_read as u32), SECCOMP_RET_ALLOW);
+        assert_eq!(run(&filter, 0x4000_0003, libc::SYS_read as u32), SECCOMP_RET_KILL_PROCESS);
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn deny_filter_kills_x32_syscalls() {
+        let filter = deny_filter(&[libc::SYS_mount]);
+        let x32_mount = X32_SYSCALL_BIT | libc::SYS_mount as u32;
+        assert_eq!(run(&filter, AUDIT_ARCH, x32_mount), SECCOMP_RET_KILL_PROCESS);
+        assert_eq!(run(&filter, AUDIT_ARCH, X32_SYSCALL_BIT), SECCOMP_RET_KILL_PROCESS);
+    }
+}
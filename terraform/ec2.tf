@@ -48,7 +48,9 @@ resource "aws_instance" "enclave_instance" {
   ]
 
   user_data = base64encode(templatefile("${path.module}/user-data.sh.tpl", {
-    eifArtifactPath = var.eif_artifact_path
+    eifArtifactPath  = var.eif_artifact_path
+    hostArtifactPath = coalesce(var.host_artifact_path, var.eif_artifact_path)
+    hostConfig       = file("${path.module}/../src/host/enclave-host.toml")
   }))
 
   enclave_options {
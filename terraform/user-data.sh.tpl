@@ -20,15 +20,41 @@ tar -zxf oras_$${VERSION}_*.tar.gz -C /root/oras-install/
 mv /root/oras-install/oras /usr/local/bin/
 rm -rf /root/oras_$${VERSION}_*.tar.gz /root/oras-install/
 
-# Install socat for forwarding
-yum install socat -y
+# Install nftables-backed iptables for the enclave egress NAT
+dnf install iptables-nft -y
 
 # Authenticate and pull the EIF artifact from Amazon ECR
 aws ecr get-login-password --region us-east-2 | docker login --username AWS --password-stdin 717279690196.dkr.ecr.us-east-2.amazonaws.com
 HOME=/root oras pull -o /root ${eifArtifactPath}
-
-# Run socat to forward traffic
-socat -t 30 TCP-LISTEN:80,fork,reuseaddr VSOCK-CONNECT:7777:1000 &
+%{ if hostArtifactPath != eifArtifactPath ~}
+HOME=/root oras pull -o /root ${hostArtifactPath}
+%{ endif ~}
+install -m 0755 /root/enclave-host /usr/local/bin/enclave-host
+
+# Host companion daemon: inbound forwarding, enclave egress, logs and the
+# KMS proxy, configured from src/host/enclave-host.toml
+cat > /etc/enclave-host.toml <<'CONFIG'
+${hostConfig}
+CONFIG
+cat > /etc/systemd/system/enclave-host.service <<'UNIT'
+[Unit]
+Description=Enclave host companion daemon
+After=network-online.target nitro-enclaves-allocator.service
+
+[Service]
+ExecStart=/usr/local/bin/enclave-host /etc/enclave-host.toml
+Restart=always
+RestartSec=2
+
+[Install]
+WantedBy=multi-user.target
+UNIT
+systemctl daemon-reload
+systemctl enable --now enclave-host
+
+# Route enclave traffic arriving on the egress TAP device out of the instance
+sysctl -w net.ipv4.ip_forward=1
+iptables -t nat -A POSTROUTING -s 192.168.127.0/24 ! -o enclave0 -j MASQUERADE
 
 # Start the Nitro Enclave using the pulled EIF
 nitro-cli run-enclave --cpu-count 2 --memory 512 --enclave-name test --enclave-cid 7777 --eif-path /root/enclave.eif --debug-mode
@@ -7,3 +7,9 @@ variable "eif_artifact_path" {
   type        = string
   description = "The full OCI path of the EIF"
 }
+
+variable "host_artifact_path" {
+  type        = string
+  default     = null
+  description = "The full OCI path of the enclave-host daemon binary, by default the EIF artifact CI publishes it in"
+}